@@ -1,7 +1,8 @@
 pub const CELL_SIZE: f64 = 10.0;
 pub const CELL_SQUARE: [f64; 4] = [0.0, 0.0, CELL_SIZE - 1.0, CELL_SIZE - 1.0];
 
-pub const WINDOW_UPS: u64 = 5;
+// for games timing their own moves from `UpdateArgs.dt`
+pub const TIMER_UPS: u64 = 60;
 pub const WINDOW_HEIGHT: u32 = 300;
 pub const WINDOW_WIDTH: u32 = 300;
 
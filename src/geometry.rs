@@ -0,0 +1,244 @@
+use std::ops::{Add, AddAssign, Sub};
+
+/// A cell of a board, `x` to the right and `y` down.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Point(pub i64, pub i64);
+
+impl Add<Point> for Point {
+    type Output = Point;
+    fn add(self, rhs: Point) -> Point {
+        Point(self.0 + rhs.0, self.1 + rhs.1)
+    }
+}
+
+impl AddAssign<Point> for Point {
+    fn add_assign(&mut self, rhs: Point) {
+        self.0 += rhs.0;
+        self.1 += rhs.1;
+    }
+}
+
+impl Sub<Point> for Point {
+    type Output = Point;
+    fn sub(self, rhs: Point) -> Point {
+        Point(self.0 - rhs.0, self.1 - rhs.1)
+    }
+}
+
+impl Point {
+    /// Turned a quarter counterclockwise around the origin, as seen on screen.
+    pub fn rotate_ccw(&self) -> Point {
+        Point(self.1, -self.0)
+    }
+
+    /// The four cells sharing a side with this one, clockwise from above.
+    pub fn neighbors(&self) -> [Point; 4] {
+        [*self + Direction::Up.delta(),
+         *self + Direction::Right.delta(),
+         *self + Direction::Down.delta(),
+         *self + Direction::Left.delta()]
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// Every direction, clockwise from `Up`.
+pub const DIRECTIONS: [Direction; 4] = [Direction::Up, Direction::Right, Direction::Down, Direction::Left];
+
+impl Direction {
+    pub fn delta(&self) -> Point {
+        match *self {
+            Direction::Left => Point(-1, 0),
+            Direction::Right => Point(1, 0),
+            Direction::Up => Point(0, -1),
+            Direction::Down => Point(0, 1),
+        }
+    }
+
+    pub fn opposite(&self) -> Direction {
+        match *self {
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+        }
+    }
+}
+
+/// A block of cells, `left` and `top` included.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Rect {
+    pub left: i64,
+    pub top: i64,
+    pub width: i64,
+    pub height: i64,
+}
+
+impl Rect {
+    pub fn new(left: i64, top: i64, width: i64, height: i64) -> Rect {
+        Rect {
+            left: left,
+            top: top,
+            width: width,
+            height: height,
+        }
+    }
+
+    /// The `width` by `height` block starting at the origin.
+    pub fn sized(width: u32, height: u32) -> Rect {
+        Rect::new(0, 0, width as i64, height as i64)
+    }
+
+    pub fn right(&self) -> i64 {
+        self.left + self.width
+    }
+
+    pub fn bottom(&self) -> i64 {
+        self.top + self.height
+    }
+
+    pub fn contains(&self, p: Point) -> bool {
+        p.0 >= self.left && p.1 >= self.top && p.0 < self.right() && p.1 < self.bottom()
+    }
+
+    /// Every cell, row by row.
+    pub fn points(&self) -> Vec<Point> {
+        let mut points = Vec::new();
+        for y in self.top..self.bottom() {
+            for x in self.left..self.right() {
+                points.push(Point(x, y));
+            }
+        }
+        points
+    }
+
+    /// `p` moved back inside, leaving on one side and coming in on the other.
+    pub fn wrap(&self, p: Point) -> Point {
+        let (x, y) = (p.0 - self.left, p.1 - self.top);
+        Point(self.left + ((x % self.width) + self.width) % self.width,
+              self.top + ((y % self.height) + self.height) % self.height)
+    }
+}
+
+/// One `T` for every cell of a `width` by `height` board, stored row by row.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Grid<T> {
+    bounds: Rect,
+    cells: Vec<T>,
+}
+
+impl<T: Clone> Grid<T> {
+    pub fn new(width: u32, height: u32, value: T) -> Grid<T> {
+        Grid {
+            bounds: Rect::sized(width, height),
+            cells: vec![value; (width * height) as usize],
+        }
+    }
+}
+
+impl<T> Grid<T> {
+    pub fn bounds(&self) -> Rect {
+        self.bounds
+    }
+
+    pub fn contains(&self, p: Point) -> bool {
+        self.bounds.contains(p)
+    }
+
+    /// Where `p` is stored, `None` when it is off the board.
+    pub fn index(&self, p: Point) -> Option<usize> {
+        if self.contains(p) {
+            Some((p.1 * self.bounds.width + p.0) as usize)
+        } else {
+            None
+        }
+    }
+
+    /// The cell stored at `index`.
+    pub fn point(&self, index: usize) -> Point {
+        let i = index as i64;
+        Point(i % self.bounds.width, i / self.bounds.width)
+    }
+
+    pub fn get(&self, p: Point) -> Option<&T> {
+        self.index(p).map(|i| &self.cells[i])
+    }
+
+    pub fn get_mut(&mut self, p: Point) -> Option<&mut T> {
+        match self.index(p) {
+            Some(i) => Some(&mut self.cells[i]),
+            None => None,
+        }
+    }
+
+    /// Sets the cell at `p`, returning false when it is off the board.
+    pub fn set(&mut self, p: Point, value: T) -> bool {
+        match self.get_mut(p) {
+            Some(cell) => {
+                *cell = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The neighbours of `p` that are on the board.
+    pub fn neighbors(&self, p: Point) -> Vec<Point> {
+        p.neighbors().iter().cloned().filter(|n| self.contains(*n)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_negative_coordinates() {
+        let rect = Rect::sized(5, 4);
+        assert_eq!(rect.wrap(Point(-1, 4)), Point(4, 0));
+        assert_eq!(rect.wrap(Point(-6, -1)), Point(4, 3));
+        assert_eq!(rect.wrap(Point(-10, -8)), Point(0, 0));
+        assert_eq!(rect.wrap(Point(2, 1)), Point(2, 1));
+
+        let rect = Rect::new(-2, 3, 4, 2);
+        assert_eq!(rect.wrap(Point(-3, 2)), Point(1, 4));
+        assert_eq!(rect.wrap(Point(2, 5)), Point(-2, 3));
+        assert!(rect.points().iter().all(|p| rect.wrap(*p) == *p));
+    }
+
+    #[test]
+    fn grid_index_point_round_trip() {
+        let grid = Grid::new(7, 3, 0);
+        for (i, p) in grid.bounds().points().into_iter().enumerate() {
+            assert_eq!(grid.index(p), Some(i));
+            assert_eq!(grid.point(i), p);
+        }
+        assert_eq!(grid.index(Point(-1, 0)), None);
+        assert_eq!(grid.index(Point(7, 0)), None);
+        assert_eq!(grid.index(Point(0, 3)), None);
+    }
+
+    #[test]
+    fn rotate_quarter_turns() {
+        assert_eq!(Point(1, 0).rotate_ccw(), Point(0, -1));
+        let p = Point(2, -3);
+        assert_eq!(p.rotate_ccw().rotate_ccw().rotate_ccw().rotate_ccw(), p);
+        // counterclockwise is the previous of the directions listed clockwise
+        for (i, d) in DIRECTIONS.iter().enumerate() {
+            assert_eq!(d.delta().rotate_ccw(), DIRECTIONS[(i + 3) % 4].delta());
+        }
+    }
+
+    #[test]
+    fn grid_neighbors_stay_on_the_board() {
+        let grid = Grid::new(3, 2, false);
+        assert_eq!(grid.neighbors(Point(0, 0)), vec![Point(1, 0), Point(0, 1)]);
+        assert_eq!(grid.neighbors(Point(1, 1)), vec![Point(1, 0), Point(2, 1), Point(0, 1)]);
+    }
+}
@@ -0,0 +1,11 @@
+//! The engines of the games, shared by their binaries.
+
+extern crate rand;
+
+pub mod geometry;
+pub mod tetris;
+pub mod snake;
+pub mod snake_level;
+pub mod snake_ai;
+pub mod space_invaders;
+pub mod presets;
@@ -3,15 +3,35 @@
 
 extern crate piston_window;
 extern crate find_folder;
-extern crate rand;
+extern crate piston_games;
 
-mod snake;
 mod consts;
 
 use piston_window::*;
-use snake::*;
+use piston_games::snake::*;
+use piston_games::snake_level::Level;
+use piston_games::snake_ai::{Autopilot, Bot, Difficulty};
+use piston_games::presets::{self, Preset, Presets};
 use consts::*;
 
+// head and body color of each player
+const PLAYER_COLORS: [([f32; 4], [f32; 4]); MAX_PLAYERS] = [(NEW_RED, color::BLACK),
+                                                            ([0.0, 0.8, 0.0, 1.0], [0.0, 0.4, 0.0, 1.0]),
+                                                            ([1.0, 0.8, 0.0, 1.0], [0.6, 0.5, 0.0, 1.0]),
+                                                            ([0.8, 0.0, 0.8, 1.0], [0.4, 0.0, 0.4, 1.0])];
+
+// the opponents of player 1 in a battle, one of each kind
+const BOT_DIFFICULTIES: [Difficulty; 3] = [Difficulty::RandomWalk, Difficulty::Greedy, Difficulty::PathPlanning];
+
+const PORTAL_COLORS: [[f32; 4]; 3] = [[0.0, 1.0, 1.0, 1.0], [1.0, 0.0, 1.0, 1.0], [1.0, 1.0, 0.0, 1.0]];
+
+fn pace(preset: &Preset) -> Pace {
+    Pace {
+        interval_secs: preset.snake_interval_secs,
+        food_lifetime: preset.food_lifetime,
+    }
+}
+
 #[derive(PartialEq)]
 enum GameState {
     Init,
@@ -22,8 +42,18 @@ enum GameState {
 
 struct App {
     snake: Snake,
+    boundary: Boundary,
+    mode: Mode,
+    presets: Presets,
+    difficulty: presets::Difficulty,
+    levels: Vec<Level>,
+    level_index: Option<usize>,
+    autopilot: Option<Autopilot>,
+    bots: Vec<Bot>,
+    elapsed: f64,
+    best_score: u32,
     game_state: GameState,
-    message: &'static str,
+    results: Vec<String>,
     cache: Glyphs,
 }
 
@@ -31,24 +61,28 @@ impl App {
     fn handle_press(&mut self, key: &keyboard::Key) {
         match *key {
             Key::Up if self.game_state == GameState::Playing => {
-                if self.snake.direction != Direction::Down {
-                    self.snake.direction = Direction::Up;
-                }
+                self.snake.turn(0, Direction::Up);
             },
             Key::Down if self.game_state == GameState::Playing => {
-                if self.snake.direction != Direction::Up {
-                    self.snake.direction = Direction::Down;
-                }
+                self.snake.turn(0, Direction::Down);
             },
             Key::Left if self.game_state == GameState::Playing => {
-                if self.snake.direction != Direction::Right {
-                    self.snake.direction = Direction::Left;
-                }
+                self.snake.turn(0, Direction::Left);
             },
             Key::Right if self.game_state == GameState::Playing => {
-                if self.snake.direction != Direction::Left {
-                    self.snake.direction = Direction::Right;
-                }
+                self.snake.turn(0, Direction::Right);
+            },
+            Key::W if self.game_state == GameState::Playing && self.bots.is_empty() => {
+                self.snake.turn(1, Direction::Up);
+            },
+            Key::S if self.game_state == GameState::Playing && self.bots.is_empty() => {
+                self.snake.turn(1, Direction::Down);
+            },
+            Key::A if self.game_state == GameState::Playing && self.bots.is_empty() => {
+                self.snake.turn(1, Direction::Left);
+            },
+            Key::D if self.game_state == GameState::Playing && self.bots.is_empty() => {
+                self.snake.turn(1, Direction::Right);
             },
             Key::P if self.game_state == GameState::Playing => {
                 self.game_state = GameState::Paused;
@@ -56,19 +90,76 @@ impl App {
             Key::R if self.game_state == GameState::Paused => {
                 self.game_state = GameState::Playing;
             },
+            Key::W if self.game_state == GameState::Init => {
+                self.boundary = match self.boundary {
+                    Boundary::Walls => Boundary::Wrap,
+                    Boundary::Wrap => Boundary::Walls,
+                };
+            },
+            Key::D if self.game_state == GameState::Init => {
+                self.difficulty = self.difficulty.next();
+            },
+            Key::M if self.game_state == GameState::Init => {
+                self.mode = self.mode.next();
+            },
+            Key::E if self.game_state == GameState::Playing && self.snake.mode == Mode::Zen => {
+                self.finish("Zen session over...");
+            },
             Key::N => {
-                self.snake = Snake::new_with_bounds(self.snake.width, self.snake.height);
-                self.game_state = GameState::Playing;
+                let snake = Snake::new_with_rules(self.snake.width, self.snake.height, self.rules());
+                self.start(snake, None, false);
+            },
+            Key::D2 => {
+                let snake = Snake::new_with_players(self.snake.width, self.snake.height, 2, self.rules());
+                self.start(snake, None, false);
+            },
+            Key::L if !self.levels.is_empty() => {
+                let snake = Snake::new_with_level(&self.levels[0], self.rules());
+                self.start(snake, Some(0), false);
+            },
+            Key::A if self.game_state != GameState::Playing => {
+                let snake = Snake::new_with_rules(self.snake.width, self.snake.height, self.rules());
+                self.start(snake, None, true);
+            },
+            Key::B if self.game_state != GameState::Playing => {
+                let players = BOT_DIFFICULTIES.len() + 1;
+                let snake = Snake::new_with_players(self.snake.width, self.snake.height, players, self.rules());
+                self.start(snake, None, false);
+                self.bots = BOT_DIFFICULTIES.iter()
+                    .enumerate()
+                    .map(|(i, d)| Bot::new(&self.snake, i + 1, *d))
+                    .collect();
             },
             _ => {},
         }
     }
 
+    // The boundary, mode and difficulty picked on the start screen, for every kind of game.
+    fn rules(&self) -> Rules {
+        Rules {
+            boundary: self.boundary,
+            mode: self.mode,
+            pace: pace(&self.presets.get(self.difficulty)),
+        }
+    }
+
+    fn start(&mut self, snake: Snake, level_index: Option<usize>, autopilot: bool) {
+        self.autopilot = if autopilot { Some(Autopilot::new(&snake, 0)) } else { None };
+        self.bots.clear();
+        self.snake = snake;
+        self.level_index = level_index;
+        self.elapsed = 0.0;
+        self.game_state = GameState::Playing;
+    }
+
     fn handle_draw(&mut self, c: &Context, g: &mut G2d) {
         if self.game_state == GameState::GameOver {
             clear(color::WHITE, g);
-            text(NEW_RED, FONT_SIZE, self.message,
-                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN), g);
+            for (i, line) in self.results.iter().enumerate() {
+                let line_color = if i == 0 { NEW_RED } else { color::BLACK };
+                text(line_color, FONT_SIZE, line,
+                    &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * i as f64), g);
+            }
         } else {
             clear(color::grey(0.6), g);
 
@@ -76,14 +167,71 @@ impl App {
                 c.transform.trans(self.snake.egg.0 as f64 * CELL_SIZE,
                 self.snake.egg.1 as f64 * CELL_SIZE), g);
 
-            ellipse(NEW_RED, CELL_SQUARE,
-                c.transform.trans(self.snake.head.0 as f64 * CELL_SIZE,
-                self.snake.head.1 as f64 * CELL_SIZE), g);
+            for food in &self.snake.foods {
+                let transform = c.transform.trans(food.pos.0 as f64 * CELL_SIZE,
+                    food.pos.1 as f64 * CELL_SIZE);
+                match food.kind {
+                    FoodKind::Egg => ellipse(NEW_BLUE, CELL_SQUARE, transform, g),
+                    FoodKind::Bonus => ellipse([1.0, 0.8, 0.0, 1.0], CELL_SQUARE, transform, g),
+                    FoodKind::Poison => rectangle([0.5, 0.0, 0.5, 1.0], CELL_SQUARE, transform, g),
+                    FoodKind::Faster => rectangle([1.0, 0.5, 0.0, 1.0], CELL_SQUARE, transform, g),
+                    FoodKind::Slower => rectangle([0.0, 0.6, 0.0, 1.0], CELL_SQUARE, transform, g),
+                }
+            }
 
-            for p in &self.snake.body {
-                rectangle(color::BLACK, CELL_SQUARE,
+            for p in &self.snake.walls {
+                rectangle(color::grey(0.3), CELL_SQUARE,
                     c.transform.trans(p.0 as f64 * CELL_SIZE, p.1 as f64 * CELL_SIZE), g);
             }
+
+            for (i, &(a, b)) in self.snake.portals.iter().enumerate() {
+                let portal_color = PORTAL_COLORS[i % PORTAL_COLORS.len()];
+                for p in &[a, b] {
+                    Ellipse::new_border(portal_color, 1.5).draw(CELL_SQUARE, &c.draw_state,
+                        c.transform.trans(p.0 as f64 * CELL_SIZE, p.1 as f64 * CELL_SIZE), g);
+                }
+            }
+
+            for (player, &(head_color, body_color)) in self.snake.players.iter().zip(PLAYER_COLORS.iter()) {
+                if !player.alive() {
+                    continue;
+                }
+
+                ellipse(head_color, CELL_SQUARE,
+                    c.transform.trans(player.head.0 as f64 * CELL_SIZE,
+                    player.head.1 as f64 * CELL_SIZE), g);
+
+                for p in &player.body {
+                    rectangle(body_color, CELL_SQUARE,
+                        c.transform.trans(p.0 as f64 * CELL_SIZE, p.1 as f64 * CELL_SIZE), g);
+                }
+            }
+
+            text(color::WHITE, FONT_SIZE, &self.scores(),
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN), g);
+            text(color::WHITE, FONT_SIZE,
+                &format!("Length: {}  Speed: {}", self.snake.players[0].len(), self.snake.speed()),
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT), g);
+
+            if let (Some(i), Some(target)) = (self.level_index, self.snake.target_length) {
+                text(color::WHITE, FONT_SIZE,
+                    &format!("Level {} ({}/{})", i + 1, self.snake.players[0].len(), target),
+                    &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 2.0), g);
+            }
+
+            let mode_info = match self.snake.mode {
+                Mode::Classic => None,
+                Mode::TimeAttack => Some(format!("Time left: {:.0}", self.snake.time_left().unwrap_or(0.0))),
+                Mode::Survival => {
+                    let (w, h) = self.snake.arena_size();
+                    Some(format!("Time: {:.0}  Arena: {}x{}", self.snake.elapsed(), w, h))
+                },
+                Mode::Zen => Some("Zen: press `E` to End".to_string()),
+            };
+            if let Some(info) = mode_info {
+                text(color::WHITE, FONT_SIZE, &info,
+                    &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 2.0), g);
+            }
         }
 
         if self.game_state != GameState::Playing {
@@ -96,19 +244,119 @@ impl App {
             text(color::BLACK, FONT_SIZE, "Press `ESC` to Exit",
                 &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 6.0), g);
         }
+
+        if self.game_state == GameState::Init {
+            let boundary = match self.boundary {
+                Boundary::Walls => "Press `W` to toggle boundary: Walls",
+                Boundary::Wrap => "Press `W` to toggle boundary: Wrap",
+            };
+            text(color::BLACK, FONT_SIZE, boundary,
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 7.0), g);
+            text(color::BLACK, FONT_SIZE, "Press `L` to play the Levels",
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 8.0), g);
+            text(color::BLACK, FONT_SIZE, "Press `2` for 2 players (WASD)",
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 9.0), g);
+            text(color::BLACK, FONT_SIZE, "Press `A` for the Autopilot demo",
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 10.0), g);
+            text(color::BLACK, FONT_SIZE, "Press `B` for a Battle against bots",
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 11.0), g);
+            text(color::BLACK, FONT_SIZE, &format!("Press `M` for mode: {}", self.mode.name()),
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 12.0), g);
+            text(color::BLACK, FONT_SIZE, &format!("Press `D` for difficulty: {}", self.difficulty.name()),
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 13.0), g);
+        }
     }
 
-    fn handle_update(&mut self, _args: &UpdateArgs) {
+    fn scores(&self) -> String {
+        if self.snake.players.len() == 1 && self.snake.mode == Mode::Classic {
+            return format!("Score: {}  Best: {}", self.snake.players[0].score, self.best_score);
+        } else if self.snake.players.len() == 1 {
+            return format!("Score: {}", self.snake.players[0].score);
+        }
+        self.snake.players.iter().enumerate()
+            .map(|(i, p)| format!("P{}: {}", i + 1, p.score))
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
+
+    fn handle_update(&mut self, args: &UpdateArgs) {
         if self.game_state == GameState::Playing {
-            match self.snake.move_on() {
-                Ok(_) => {},
-                Err(message) => {
-                    self.game_state = GameState::GameOver;
-                    self.message = message;
-                },
+            self.elapsed += args.dt;
+        }
+        while self.game_state == GameState::Playing && self.elapsed >= self.snake.move_interval() {
+            self.elapsed -= self.snake.move_interval();
+            self.tick();
+        }
+    }
+
+    fn tick(&mut self) {
+        let autopilot = &self.autopilot;
+        let bots = &self.bots;
+        let moved = self.snake.move_on_with(|snake, player| {
+            match bots.iter().find(|bot| bot.player() == player) {
+                Some(bot) => Some(bot.choose(snake)),
+                None if player == 0 => autopilot.as_ref().map(|autopilot| autopilot.choose(snake)),
+                None => None,
             }
+        });
+
+        match moved {
+            Ok(_) => {},
+            Err(game_over) => self.finish(game_over.message()),
+        }
+
+        // no point in watching the bots finish the battle
+        if self.game_state == GameState::Playing && !self.bots.is_empty() && !self.snake.players[0].alive() {
+            self.finish("You lose! The bots got you...");
+        }
+
+        if self.autopilot.is_none() && self.snake.players.len() == 1 && self.snake.mode == Mode::Classic {
+            self.best_score = self.best_score.max(self.snake.players[0].score);
+        }
+
+        if self.game_state == GameState::Playing && self.snake.level_complete() {
+            self.next_level();
         }
     }
+
+    fn next_level(&mut self) {
+        let next = self.level_index.map_or(0, |i| i + 1);
+        if next < self.levels.len() {
+            self.snake = Snake::new_with_level(&self.levels[next], self.rules());
+            self.level_index = Some(next);
+        } else {
+            self.finish("You win! All levels cleared...");
+        }
+    }
+
+    fn finish(&mut self, headline: &str) {
+        self.game_state = GameState::GameOver;
+        self.results = self.results(headline);
+    }
+
+    // What the game over screen shows for the mode just played.
+    fn results(&self, headline: &str) -> Vec<String> {
+        let player = &self.snake.players[0];
+        let details = match self.snake.mode {
+            Mode::Classic => vec![self.scores()],
+            Mode::TimeAttack => {
+                let seconds = self.snake.elapsed().min(TIME_ATTACK_SECS);
+                vec![format!("{} eggs in {:.0} seconds", player.eggs, seconds), self.scores()]
+            },
+            Mode::Survival => {
+                let (w, h) = self.snake.arena_size();
+                vec![format!("Survived {:.0} seconds", self.snake.elapsed()),
+                     format!("Arena left: {}x{}", w, h)]
+            },
+            Mode::Zen => {
+                vec![format!("{} eggs in {:.0} seconds", player.eggs, self.snake.elapsed()),
+                     format!("Length: {}", player.len())]
+            },
+        };
+        let mut lines = vec![headline.to_string()];
+        lines.extend(details);
+        lines
+    }
 }
 
 fn main() {
@@ -119,18 +367,36 @@ fn main() {
                                     .unwrap_or_else(|e| {
                                         panic!("Faild build PistonWindow: {:?}", e);
                                     });
-    window.set_ups(WINDOW_UPS);
+    window.set_ups(TIMER_UPS);
 
     // println!("{:?}", window.size());
     let assets = find_folder::Search::KidsThenParents(3, 5)
                     .for_folder("assets")
                     .unwrap();
     let font_path = assets.join("fonts/NotoSans/NotoSans-Bold.ttf");
+    let levels = Level::load_dir(&assets.join("levels")).unwrap_or_else(|e| {
+        println!("Failed to load the levels: {}", e);
+        Vec::new()
+    });
+    let presets = Presets::load(&assets.join("presets.txt")).unwrap_or_else(|e| {
+        println!("Failed to load the presets: {}", e);
+        Presets::builtin()
+    });
 
     let mut app = App {
         snake: Snake::new_with_bounds(WINDOW_WIDTH / (CELL_SIZE as u32), WINDOW_HEIGHT / (CELL_SIZE as u32)),
+        boundary: Boundary::Walls,
+        mode: Mode::Classic,
+        presets: presets,
+        difficulty: presets::Difficulty::Normal,
+        levels: levels,
+        level_index: None,
+        autopilot: None,
+        bots: Vec::new(),
+        elapsed: 0.0,
+        best_score: 0,
         game_state: GameState::Init,
-        message: "no message",
+        results: Vec::new(),
         cache: Glyphs::new(&font_path, window.factory.clone()).unwrap(),
     };
 
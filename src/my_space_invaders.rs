@@ -3,15 +3,59 @@
 
 extern crate piston_window;
 extern crate find_folder;
-extern crate rand;
+extern crate piston_games;
 
-mod space_invaders;
 mod consts;
 
 use piston_window::*;
-use space_invaders::*;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use piston_games::space_invaders::*;
+use piston_games::presets::{Difficulty, Preset, Presets};
 use consts::*;
 
+// A missing or unreadable file just means no high score yet.
+fn load_high_score(path: &Path) -> u32 {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .ok()
+        .and_then(|_| contents.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+fn save_high_score(path: &Path, score: u32) -> Result<(), String> {
+    File::create(path)
+        .and_then(|mut file| writeln!(file, "{}", score))
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn settings(preset: &Preset) -> Settings {
+    Settings {
+        lives: preset.lives,
+        bunkers_persist: preset.bunkers_persist,
+        fire_ticks: preset.fire_ticks,
+        march_ticks: preset.march_ticks,
+        ammo: Ammo::new(preset.magazine, preset.reload_secs, preset.cooldown_secs, preset.max_on_screen),
+    }
+}
+
+// Moves the cell-sized shapes drawn from the origin over `center`.
+fn transform_at(c: &Context, center: Vec2) -> math::Matrix2d {
+    c.transform.trans((center.0 - 0.5) * CELL_SIZE, (center.1 - 0.5) * CELL_SIZE)
+}
+
+fn power_up_color(kind: PowerUpKind) -> [f32; 4] {
+    match kind {
+        PowerUpKind::Spread => [1.0, 0.5, 0.0, 1.0],
+        PowerUpKind::Rapid => [1.0, 1.0, 0.0, 1.0],
+        PowerUpKind::Piercing => [0.0, 1.0, 1.0, 1.0],
+        PowerUpKind::Shield => color::WHITE,
+        PowerUpKind::Ammo => [0.0, 0.8, 0.0, 1.0],
+    }
+}
+
 #[derive(PartialEq)]
 enum GameState {
     Init,
@@ -22,6 +66,16 @@ enum GameState {
 
 struct App {
     space_invaders: SpaceInvaders,
+    presets: Presets,
+    difficulty: Difficulty,
+    high_score: u32,
+    high_score_path: PathBuf,
+    // the game just over beat the high score
+    new_record: bool,
+    // seconds played, to flash the invulnerable player
+    clock: f64,
+    // how far the next frame is between the last update and the next one
+    alpha: f64,
     game_state: GameState,
     message: &'static str,
     cache: Glyphs,
@@ -33,16 +87,15 @@ impl App {
         match *key {
             Key::Up if self.game_state == GameState::Playing => {
                 self.space_invaders.shoot();
-                self.space_invaders.gen_egg();
             },
             Key::Down if self.game_state == GameState::Playing => {
-                self.space_invaders.gen_egg();
+                self.space_invaders.reload();
             },
             Key::Left if self.game_state == GameState::Playing => {
-                self.space_invaders.head_left();
+                self.space_invaders.steer(Direction::Left);
             },
             Key::Right if self.game_state == GameState::Playing => {
-                self.space_invaders.head_right();
+                self.space_invaders.steer(Direction::Right);
             },
             Key::P if self.game_state == GameState::Playing => {
                 self.game_state = GameState::Paused;
@@ -50,39 +103,136 @@ impl App {
             Key::R if self.game_state == GameState::Paused => {
                 self.game_state = GameState::Playing;
             },
+            Key::D if self.game_state == GameState::Init => {
+                self.difficulty = self.difficulty.next();
+            },
             Key::N => {
-                self.space_invaders = SpaceInvaders::new_with_bounds(self.space_invaders.width, self.space_invaders.height);
+                self.space_invaders = SpaceInvaders::new_with_settings(self.space_invaders.width,
+                                                                       self.space_invaders.height,
+                                                                       settings(&self.presets.get(self.difficulty)));
                 self.game_state = GameState::Playing;
             },
             _ => {},
         }
     }
 
+    fn handle_release(&mut self, key: &keyboard::Key) {
+        match *key {
+            Key::Left => self.space_invaders.stop(Direction::Left),
+            Key::Right => self.space_invaders.stop(Direction::Right),
+            _ => {},
+        }
+    }
+
     fn handle_draw(&mut self, c: &Context, g: &mut G2d) {
         if self.game_state == GameState::GameOver {
             clear(color::WHITE, g);
             text(NEW_RED, FONT_SIZE, self.message,
                 &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN), g);
+            text(color::BLACK, FONT_SIZE,
+                &format!("Score: {}  Wave: {}", self.space_invaders.score, self.space_invaders.wave),
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT), g);
+            let high_score = if self.new_record {
+                format!("New high score: {}", self.high_score)
+            } else {
+                format!("High score: {}", self.high_score)
+            };
+            text(color::BLACK, FONT_SIZE, &high_score,
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 2.0), g);
         } else {
             clear(color::grey(0.6), g);
 
-            ellipse(color::BLACK, CELL_SQUARE,
-                c.transform.trans(self.space_invaders.head.0 as f64 * CELL_SIZE,
-                self.space_invaders.head.1 as f64 * CELL_SIZE), g);
+            let alpha = self.alpha;
+            let head_transform = transform_at(c, self.space_invaders.head_render_pos(alpha));
+            let blink = (self.clock / TICK_SECS) as u64 % 2 == 0;
+            if self.space_invaders.exploding() {
+                ellipse([1.0, 0.5, 0.0, 1.0], [-CELL_SIZE / 2.0, -CELL_SIZE / 2.0, CELL_SIZE * 2.0, CELL_SIZE * 2.0],
+                    head_transform, g);
+            } else if !self.space_invaders.invulnerable() || blink {
+                ellipse(color::BLACK, CELL_SQUARE, head_transform, g);
+            }
+            if self.space_invaders.has_effect(PowerUpKind::Shield) && !self.space_invaders.exploding() {
+                Ellipse::new_border(power_up_color(PowerUpKind::Shield), 1.0)
+                    .draw([-CELL_SIZE / 2.0, -CELL_SIZE / 2.0, CELL_SIZE * 2.0, CELL_SIZE * 2.0],
+                        &c.draw_state, head_transform, g);
+            }
 
-            for p in &self.space_invaders.body {
-                rectangle(color::BLACK, CELL_SQUARE,
-                    c.transform.trans(p.0 as f64 * CELL_SIZE, p.1 as f64 * CELL_SIZE), g);
+            for alien in &self.space_invaders.body {
+                let alien_color = match alien.kind {
+                    AlienKind::Squid => [0.5, 0.0, 0.5, 1.0],
+                    AlienKind::Crab => [0.0, 0.4, 0.0, 1.0],
+                    AlienKind::Octopus => color::BLACK,
+                };
+                rectangle(alien_color, CELL_SQUARE,
+                    c.transform.trans(alien.pos.0 as f64 * CELL_SIZE, alien.pos.1 as f64 * CELL_SIZE), g);
             }
 
-            for p in &self.space_invaders.eggs {
-                rectangle(NEW_RED, CELL_SQUARE,
+            for p in &self.space_invaders.bunkers {
+                rectangle([0.0, 0.6, 0.0, 1.0], CELL_SQUARE,
                     c.transform.trans(p.0 as f64 * CELL_SIZE, p.1 as f64 * CELL_SIZE), g);
             }
 
-            for p in &self.space_invaders.bullets {
-                ellipse(NEW_BLUE, CELL_SQUARE,
-                    c.transform.trans(p.0 as f64 * CELL_SIZE, p.1 as f64 * CELL_SIZE), g);
+            for egg in &self.space_invaders.eggs {
+                let transform = transform_at(c, egg.render_pos(alpha));
+                match egg.kind {
+                    EggKind::Plunger => rectangle(NEW_RED, CELL_SQUARE, transform, g),
+                    EggKind::Squiggly => rectangle([1.0, 0.5, 0.0, 1.0], CELL_SQUARE, transform, g),
+                    EggKind::Rolling => ellipse(NEW_RED, CELL_SQUARE, transform, g),
+                }
+            }
+
+            if let Some(ref ufo) = self.space_invaders.ufo {
+                ellipse(NEW_RED, [-CELL_SIZE / 2.0, 0.0, CELL_SIZE * 2.0, CELL_SIZE - 1.0],
+                    transform_at(c, ufo.render_pos(alpha)), g);
+            }
+
+            if let Some((p, points)) = self.space_invaders.ufo_score() {
+                text(NEW_RED, FONT_SIZE, &points.to_string(),
+                    &mut self.cache, c.transform.trans(p.0 as f64 * CELL_SIZE, (p.1 + 1) as f64 * CELL_SIZE), g);
+            }
+
+            for bullet in &self.space_invaders.bullets {
+                let bullet_color = if bullet.piercing { power_up_color(PowerUpKind::Piercing) } else { NEW_BLUE };
+                ellipse(bullet_color, CELL_SQUARE, transform_at(c, bullet.render_pos(alpha)), g);
+            }
+
+            for power_up in &self.space_invaders.power_ups {
+                let transform = transform_at(c, power_up.render_pos(alpha));
+                rectangle(power_up_color(power_up.kind), CELL_SQUARE, transform, g);
+                Rectangle::new_border(color::BLACK, 1.0).draw(CELL_SQUARE, &c.draw_state, transform, g);
+            }
+
+            text(color::WHITE, FONT_SIZE,
+                &format!("Score: {}  High: {}", self.space_invaders.score, self.high_score.max(self.space_invaders.score)),
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN), g);
+            text(color::WHITE, FONT_SIZE,
+                &format!("Wave: {}  Lives: {}", self.space_invaders.wave, self.space_invaders.lives),
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT), g);
+
+            // a pip for every round of the magazine, under the bar of a reload
+            let ammo = self.space_invaders.settings.ammo;
+            let ammo_transform = c.transform.trans(WINDOW_WIDTH as f64 - LEFT_MARGIN - ammo.magazine as f64 * 6.0,
+                TOP_MARGIN + TEXT_HEIGHT - CELL_SIZE);
+            for i in 0..ammo.magazine {
+                let pip_color = if i < self.space_invaders.rounds() { color::WHITE } else { color::grey(0.4) };
+                rectangle(pip_color, [i as f64 * 6.0, 0.0, 4.0, CELL_SIZE - 2.0], ammo_transform, g);
+            }
+            if let Some(progress) = self.space_invaders.reload_progress() {
+                rectangle(color::WHITE, [0.0, CELL_SIZE, ammo.magazine as f64 * 6.0 * progress, 2.0], ammo_transform, g);
+            }
+
+            // an icon for every effect in play, over a bar of the time it has left
+            for (i, &(kind, ticks)) in self.space_invaders.effects().iter().enumerate() {
+                let transform = c.transform.trans(LEFT_MARGIN + i as f64 * CELL_SIZE * 3.0,
+                    TOP_MARGIN + TEXT_HEIGHT + CELL_SIZE / 2.0);
+                rectangle(power_up_color(kind), CELL_SQUARE, transform, g);
+                rectangle(power_up_color(kind),
+                    [0.0, CELL_SIZE + 1.0, CELL_SIZE * 2.5 * ticks as f64 / kind.ticks() as f64, 2.0], transform, g);
+            }
+
+            if let Some(bonus) = self.space_invaders.accuracy_bonus() {
+                text(color::WHITE, FONT_SIZE, &format!("Accuracy bonus +{}", bonus),
+                    &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 5.0), g);
             }
         }
 
@@ -97,17 +247,30 @@ impl App {
             text(color::BLACK, FONT_SIZE, "Press `ESC` to Exit",
                 &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 6.0), g);
         }
+
+        if self.game_state == GameState::Init {
+            text(color::BLACK, FONT_SIZE, &format!("Press `D` for difficulty: {}", self.difficulty.name()),
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 7.0), g);
+            text(color::BLACK, FONT_SIZE, "Press `Down` to reload",
+                &mut self.cache, c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 8.0), g);
+        }
     }
 
-    fn handle_update(&mut self, _args: &UpdateArgs) {
+    fn handle_update(&mut self, args: &UpdateArgs) {
         if self.game_state == GameState::Playing {
-            // self.duration += _args.dt;
-            // println!("{:?}", self.duration);
-            match self.space_invaders.move_on() {
+            self.clock += args.dt;
+            match self.space_invaders.update(args.dt) {
                 Ok(_) => {},
                 Err(message) => {
                     self.game_state = GameState::GameOver;
                     self.message = message;
+                    self.new_record = self.space_invaders.score > self.high_score;
+                    if self.new_record {
+                        self.high_score = self.space_invaders.score;
+                        if let Err(e) = save_high_score(&self.high_score_path, self.high_score) {
+                            println!("Failed to save the high score: {}", e);
+                        }
+                    }
                 },
             }
         }
@@ -122,16 +285,28 @@ fn main() {
                                     .unwrap_or_else(|e| {
                                         panic!("Faild build PistonWindow: {:?}", e);
                                     });
-    window.set_ups(WINDOW_UPS);
+    window.set_ups(TIMER_UPS);
 
     // println!("{:?}", window.size());
     let assets = find_folder::Search::KidsThenParents(3, 5)
                     .for_folder("assets")
                     .unwrap();
     let font_path = assets.join("fonts/NotoSans/NotoSans-Bold.ttf");
+    let high_score_path = assets.join("space_invaders_high_score.txt");
+    let presets = Presets::load(&assets.join("presets.txt")).unwrap_or_else(|e| {
+        println!("Failed to load the presets: {}", e);
+        Presets::builtin()
+    });
 
     let mut app = App {
         space_invaders: SpaceInvaders::new_with_bounds(WINDOW_WIDTH / (CELL_SIZE as u32), WINDOW_HEIGHT / (CELL_SIZE as u32)),
+        presets: presets,
+        difficulty: Difficulty::Normal,
+        high_score: load_high_score(&high_score_path),
+        high_score_path: high_score_path,
+        new_record: false,
+        clock: 0.0,
+        alpha: 0.0,
         game_state: GameState::Init,
         message: "no message",
         cache: Glyphs::new(&font_path, window.factory.clone()).unwrap(),
@@ -144,6 +319,10 @@ fn main() {
             app.handle_update(args);
         });
 
+        e.render(|args| {
+            app.alpha = (args.ext_dt * TIMER_UPS as f64).min(1.0);
+        });
+
         window.draw_2d(&e, |c, g| {
             app.handle_draw(&c, g);
         });
@@ -153,5 +332,11 @@ fn main() {
                 app.handle_press(&key);
             }
         });
+
+        e.release(|button| {
+            if let Button::Keyboard(key) = button {
+                app.handle_release(&key);
+            }
+        });
     }
 }
@@ -3,17 +3,25 @@
 
 extern crate piston_window;
 extern crate find_folder;
-extern crate rand;
+extern crate piston_games;
 // extern crate ears;
 
-mod tetris;
 mod consts;
 
 use piston_window::*;
-use tetris::*;
+use piston_games::tetris::*;
+use piston_games::presets::{Difficulty, Preset, Presets};
 use consts::*;
 // use ears::{Sound, Music, AudioController};
 
+fn speed(preset: &Preset) -> Speed {
+    Speed {
+        gravity_secs: preset.gravity_secs,
+        lock_delay_secs: preset.lock_delay_secs,
+        clear_secs: preset.clear_secs,
+    }
+}
+
 #[derive(PartialEq)]
 enum GameState {
     Init,
@@ -24,6 +32,10 @@ enum GameState {
 
 struct App {
     tetris: Tetris,
+    presets: Presets,
+    difficulty: Difficulty,
+    // seconds since the last `move_on`
+    elapsed: f64,
     game_state: GameState,
     message: &'static str,
     cache: Glyphs, 
@@ -53,8 +65,14 @@ impl App {
                 self.game_state = GameState::Playing;
                 // self.background_music.play();
             }
+            Key::D if self.game_state == GameState::Init => {
+                self.difficulty = self.difficulty.next();
+            }
             Key::N => {
-                self.tetris.reset();
+                self.tetris = Tetris::new_with_speed(self.tetris.width(),
+                                                     self.tetris.height(),
+                                                     speed(&self.presets.get(self.difficulty)));
+                self.elapsed = 0.0;
                 self.game_state = GameState::Playing;
                 // self.background_music.stop();
                 // self.background_music.play();
@@ -113,12 +131,31 @@ impl App {
                           g);
             }
 
+            let clearing = self.tetris.pending_clear().cloned();
             for p in self.tetris.bottom() {
-                rectangle(color::BLACK,
+                let cell_color = match clearing {
+                    Some(ref clear) if clear.lines.contains(&p.1) => {
+                        if clear.frames_left % 2 == 0 { color::WHITE } else { NEW_RED }
+                    }
+                    _ => color::BLACK,
+                };
+                rectangle(cell_color,
                           CELL_SQUARE,
                           c.transform.trans(p.0 as f64 * CELL_SIZE, p.1 as f64 * CELL_SIZE),
                           g);
             }
+
+            if let Some(clear) = clearing {
+                // the text floats up one cell per update while the lines flash
+                let elapsed = self.tetris.clear_frames().saturating_sub(clear.frames_left) as f64;
+                let top = clear.lines[0] as f64 - elapsed;
+                text(color::WHITE,
+                     FONT_SIZE,
+                     &format!("{} +{}", clear.name(), clear.points),
+                     &mut self.cache,
+                     c.transform.trans(LEFT_MARGIN, top * CELL_SIZE),
+                     g);
+            }
         }
 
         if self.game_state != GameState::Playing {
@@ -148,10 +185,23 @@ impl App {
                  c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 6.0),
                  g);
         }
+
+        if self.game_state == GameState::Init {
+            text(color::BLACK,
+                 FONT_SIZE,
+                 &format!("Press `D` for difficulty: {}", self.difficulty.name()),
+                 &mut self.cache,
+                 c.transform.trans(LEFT_MARGIN, TOP_MARGIN + TEXT_HEIGHT * 7.0),
+                 g);
+        }
     }
 
-    fn handle_update(&mut self, _args: &UpdateArgs) {
+    fn handle_update(&mut self, args: &UpdateArgs) {
         if self.game_state == GameState::Playing {
+            self.elapsed += args.dt;
+        }
+        while self.game_state == GameState::Playing && self.elapsed >= self.tetris.move_interval() {
+            self.elapsed -= self.tetris.move_interval();
             match self.tetris.move_on() {
                 Ok(_) => {}
                 Err(message) => {
@@ -169,16 +219,23 @@ fn main() {
         .exit_on_esc(true)
         .build()
         .unwrap();
-    window.set_ups(WINDOW_UPS);
+    window.set_ups(TIMER_UPS);
 
     let assets = find_folder::Search::KidsThenParents(3, 5)
         .for_folder("assets")
         .unwrap();
     let font_path = assets.join("fonts/NotoSans/NotoSans-Bold.ttf");
+    let presets = Presets::load(&assets.join("presets.txt")).unwrap_or_else(|e| {
+        println!("Failed to load the presets: {}", e);
+        Presets::builtin()
+    });
 
     let mut app = App {
         tetris: Tetris::new_with_bounds(WINDOW_WIDTH / (CELL_SIZE as u32),
                                         WINDOW_HEIGHT / (CELL_SIZE as u32)),
+        presets: presets,
+        difficulty: Difficulty::Normal,
+        elapsed: 0.0,
         game_state: GameState::Init,
         message: "no message",
         cache: Glyphs::new(&font_path, window.factory.clone()).unwrap(), 
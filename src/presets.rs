@@ -0,0 +1,218 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+// the presets shipped with the games, used when the file can't be read
+const BUILTIN: &'static str = include_str!("../assets/presets.txt");
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+    Custom,
+}
+
+const DIFFICULTIES: [Difficulty; 4] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard, Difficulty::Custom];
+
+impl Difficulty {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+            Difficulty::Custom => "Custom",
+        }
+    }
+
+    pub fn next(&self) -> Difficulty {
+        match *self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Custom,
+            Difficulty::Custom => Difficulty::Easy,
+        }
+    }
+}
+
+/// What one difficulty sets in each of the games.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Preset {
+    /// Tetris: seconds a piece takes to fall a row, to lock once landed, and
+    /// that cleared lines flash.
+    pub gravity_secs: f64,
+    pub lock_delay_secs: f64,
+    pub clear_secs: f64,
+    /// Snake: seconds between two moves at the first speed, and how long the
+    /// bonus items stay compared to the usual.
+    pub snake_interval_secs: f64,
+    pub food_lifetime: f64,
+    /// Space Invaders: lives, whether bunkers stay damaged between waves,
+    /// ticks between alien shots and formation steps, and the player's gun.
+    pub lives: u32,
+    pub bunkers_persist: bool,
+    pub fire_ticks: u32,
+    pub march_ticks: u32,
+    pub magazine: u32,
+    pub reload_secs: f64,
+    pub cooldown_secs: f64,
+    pub max_on_screen: usize,
+}
+
+/// The presets of every difficulty, read from a text file.
+///
+/// A `[Name]` line starts the preset of that difficulty and every
+/// `key: value` line below sets one of its values, all of which are needed
+/// and every number of which has to be above zero. Everything after a `#`
+/// is a comment.
+#[derive(Debug, Clone)]
+pub struct Presets {
+    presets: Vec<(Difficulty, Preset)>,
+}
+
+fn parse_difficulty(s: &str) -> Result<Difficulty, String> {
+    DIFFICULTIES.iter()
+        .cloned()
+        .find(|d| d.name() == s)
+        .ok_or_else(|| format!("unknown difficulty `{}`", s))
+}
+
+fn value<T: FromStr>(values: &[(String, String)], key: &str) -> Result<T, String> {
+    let value = values.iter()
+        .find(|&&(ref k, _)| k == key)
+        .map(|&(_, ref v)| v)
+        .ok_or_else(|| format!("missing `{}`", key))?;
+    value.parse().map_err(|_| format!("bad {} `{}`", key, value))
+}
+
+// Zero times hang the game loops and zero counts leave nothing to play with.
+fn positive<T: FromStr + PartialOrd + Default>(values: &[(String, String)], key: &str) -> Result<T, String> {
+    let value = value(values, key)?;
+    if value > T::default() {
+        Ok(value)
+    } else {
+        Err(format!("`{}` must be > 0", key))
+    }
+}
+
+fn parse_preset(values: &[(String, String)]) -> Result<Preset, String> {
+    Ok(Preset {
+        gravity_secs: positive(values, "tetris.gravity")?,
+        lock_delay_secs: positive(values, "tetris.lock_delay")?,
+        clear_secs: positive(values, "tetris.clear_flash")?,
+        snake_interval_secs: positive(values, "snake.interval")?,
+        food_lifetime: positive(values, "snake.food_lifetime")?,
+        lives: positive(values, "invaders.lives")?,
+        bunkers_persist: value(values, "invaders.bunkers_persist")?,
+        fire_ticks: positive(values, "invaders.fire_ticks")?,
+        march_ticks: positive(values, "invaders.march_ticks")?,
+        magazine: positive(values, "invaders.magazine")?,
+        reload_secs: positive(values, "invaders.reload")?,
+        cooldown_secs: positive(values, "invaders.cooldown")?,
+        max_on_screen: positive(values, "invaders.on_screen")?,
+    })
+}
+
+impl Presets {
+    pub fn load(path: &Path) -> Result<Presets, String> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        Presets::parse(&contents).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub fn builtin() -> Presets {
+        Presets::parse(BUILTIN).unwrap_or_else(|e| panic!("Faild parse the builtin presets: {}", e))
+    }
+
+    pub fn parse(contents: &str) -> Result<Presets, String> {
+        let mut sections: Vec<(Difficulty, Vec<(String, String)>)> = Vec::new();
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let difficulty = parse_difficulty(&line[1..line.len() - 1])?;
+                if sections.iter().any(|s| s.0 == difficulty) {
+                    return Err(format!("`{}` set twice", difficulty.name()));
+                }
+                sections.push((difficulty, Vec::new()));
+            } else if let Some(i) = line.find(':') {
+                let entry = (line[..i].trim().to_string(), line[i + 1..].trim().to_string());
+                match sections.last_mut() {
+                    Some(section) => section.1.push(entry),
+                    None => return Err(format!("`{}` outside of a preset", entry.0)),
+                }
+            } else {
+                return Err(format!("bad line `{}`", line));
+            }
+        }
+
+        let mut presets = Vec::new();
+        for difficulty in &DIFFICULTIES {
+            let values = sections.iter()
+                .find(|s| s.0 == *difficulty)
+                .map(|s| &s.1[..])
+                .ok_or_else(|| format!("no `{}` preset", difficulty.name()))?;
+            let preset = parse_preset(values).map_err(|err| format!("{}: {}", difficulty.name(), err))?;
+            presets.push((*difficulty, preset));
+        }
+        Ok(Presets { presets: presets })
+    }
+
+    pub fn get(&self, difficulty: Difficulty) -> Preset {
+        self.presets.iter().find(|p| p.0 == difficulty).map(|p| p.1).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The builtin presets with the first `from` in the `Custom` one replaced by `to`.
+    fn custom(from: &str, to: &str) -> Result<Presets, String> {
+        let i = BUILTIN.find("[Custom]").unwrap();
+        assert!(BUILTIN[i..].contains(from));
+        Presets::parse(&format!("{}{}", &BUILTIN[..i], BUILTIN[i..].replacen(from, to, 1)))
+    }
+
+    #[test]
+    fn builtin_presets_parse() {
+        let presets = Presets::builtin();
+        assert_eq!(presets.get(Difficulty::Normal).lives, 3);
+        assert!(presets.get(Difficulty::Hard).bunkers_persist);
+    }
+
+    #[test]
+    fn missing_key() {
+        assert_eq!(custom("snake.interval: 0.2\n", "").unwrap_err(), "Custom: missing `snake.interval`");
+    }
+
+    #[test]
+    fn duplicate_section() {
+        assert_eq!(custom("[Custom]", "[Hard]").unwrap_err(), "`Hard` set twice");
+    }
+
+    #[test]
+    fn unknown_difficulty() {
+        assert_eq!(custom("[Custom]", "[Insane]").unwrap_err(), "unknown difficulty `Insane`");
+    }
+
+    #[test]
+    fn zero_values() {
+        for key in &["tetris.gravity",
+                     "tetris.clear_flash",
+                     "snake.interval",
+                     "invaders.lives",
+                     "invaders.magazine",
+                     "invaders.reload",
+                     "invaders.on_screen"] {
+            let line = BUILTIN.lines().filter(|l| l.starts_with(*key)).last().unwrap();
+            assert_eq!(custom(line, &format!("{}: 0", key)).unwrap_err(),
+                       format!("Custom: `{}` must be > 0", key));
+        }
+    }
+}
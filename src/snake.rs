@@ -1,115 +1,1052 @@
 extern crate rand;
 
-use rand::Rng;
-use std::ops::Add;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::collections::VecDeque;
 
+use geometry::{Grid, Rect};
+use snake_level::Level;
+
+pub use geometry::{Direction, Point};
+
+const MAX_TURNS: usize = 3;
+const POISON_SHRINK: usize = 2;
+const EFFECT_TICKS: u32 = 30;
+const CORPSE_TICKS: u32 = 60;
+
+const SPEED_FACTOR: f64 = 0.85;
+const SEGMENTS_PER_SPEED: usize = 5;
+pub const MAX_SPEED: u32 = 10;
+
+pub const TIME_ATTACK_SECS: f64 = 60.0;
+// seconds between two rings of walls closing in, and the smallest arena left
+const SURVIVAL_SECS: f64 = 10.0;
+const MIN_ARENA: i64 = 8;
+
+/// How fast the snakes move at the first speed, and how long the bonus
+/// items stay compared to their usual lifetime.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Pace {
+    pub interval_secs: f64,
+    pub food_lifetime: f64,
+}
+
+impl Default for Pace {
+    fn default() -> Pace {
+        Pace {
+            interval_secs: 0.2,
+            food_lifetime: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FoodKind {
+    Egg,
+    Bonus,
+    Poison,
+    Faster,
+    Slower,
+}
+
+const FOOD_KINDS: [FoodKind; 5] = [FoodKind::Egg,
+                                   FoodKind::Bonus,
+                                   FoodKind::Poison,
+                                   FoodKind::Faster,
+                                   FoodKind::Slower];
+
+impl FoodKind {
+    /// Chance of the kind being rolled after an egg is eaten. Rolling `Egg`
+    /// spawns nothing extra, as the normal egg is always on the board.
+    pub fn weight(&self) -> u32 {
+        match *self {
+            FoodKind::Egg => 50,
+            FoodKind::Bonus => 20,
+            FoodKind::Poison => 15,
+            FoodKind::Faster => 8,
+            FoodKind::Slower => 7,
+        }
+    }
+
+    /// Ticks before the item disappears, `None` if it never expires.
+    pub fn lifetime(&self) -> Option<u32> {
+        match *self {
+            FoodKind::Egg => None,
+            FoodKind::Bonus => Some(30),
+            FoodKind::Poison => Some(50),
+            FoodKind::Faster | FoodKind::Slower => Some(40),
+        }
+    }
+
+    pub fn points(&self) -> u32 {
+        match *self {
+            FoodKind::Egg => 10,
+            FoodKind::Bonus => 50,
+            FoodKind::Poison => 0,
+            FoodKind::Faster | FoodKind::Slower => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Food {
+    pub kind: FoodKind,
+    pub pos: Point,
+    pub ticks_left: Option<u32>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Boundary {
+    Walls,
+    Wrap,
+}
+
+/// What ends a game, picked before it starts.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Mode {
+    Classic,
+    /// Eat as many eggs as possible in `TIME_ATTACK_SECS`.
+    TimeAttack,
+    /// Rings of walls close in from the border over time.
+    Survival,
+    /// Nothing kills the snake, the arena always wraps.
+    Zen,
+}
+
+impl Mode {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Mode::Classic => "Classic",
+            Mode::TimeAttack => "Time Attack",
+            Mode::Survival => "Survival",
+            Mode::Zen => "Zen",
+        }
+    }
+
+    pub fn next(&self) -> Mode {
+        match *self {
+            Mode::Classic => Mode::TimeAttack,
+            Mode::TimeAttack => Mode::Survival,
+            Mode::Survival => Mode::Zen,
+            Mode::Zen => Mode::Classic,
+        }
+    }
+}
+
+/// How a game is played, picked before it starts.
 #[derive(Debug, PartialEq, Clone, Copy)]
-pub struct Point(pub i64, pub i64);
+pub struct Rules {
+    pub boundary: Boundary,
+    pub mode: Mode,
+    pub pace: Pace,
+}
 
-impl Add<Point> for Point {
-    type Output = Point;
-    fn add(self, rhs: Point) -> Point {
-        Point(self.0 + rhs.0, self.1 + rhs.1)
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            boundary: Boundary::Walls,
+            mode: Mode::Classic,
+            pace: Pace::default(),
+        }
     }
 }
-//
-// impl AddAssign<Point> for Point {
-//     fn add_assign(&mut self, rhs: Point) {
-//         self.0 += rhs.0;
-//         self.1 += rhs.1;
-//     }
-// }
 
-#[derive(Debug, PartialEq)]
-pub enum Direction {
-    Left,
-    Right,
-    Up,
-    Down,
+pub const MAX_PLAYERS: usize = 4;
+
+const WIN_MESSAGES: [&'static str; MAX_PLAYERS] = ["Player 1 wins!",
+                                                   "Player 2 wins!",
+                                                   "Player 3 wins!",
+                                                   "Player 4 wins!"];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Death {
+    HitTail,
+    HitWall,
+    HitObstacle,
+    HitHead,
+    HitSnake,
+}
+
+impl Death {
+    pub fn message(&self) -> &'static str {
+        match *self {
+            Death::HitTail => "HitTail...",
+            Death::HitWall => "HitWall...",
+            Death::HitObstacle => "HitObstacle...",
+            Death::HitHead => "HitHead...",
+            Death::HitSnake => "HitSnake...",
+        }
+    }
+}
+
+/// How a game ended, returned by `Snake::move_on`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum GameOver {
+    /// There is no free cell left for the egg.
+    BoardFull,
+    Died(Death),
+    Winner(usize),
+    Draw,
+    TimeUp,
+}
+
+impl GameOver {
+    pub fn message(&self) -> &'static str {
+        match *self {
+            GameOver::BoardFull => "You win! The board is full...",
+            GameOver::Died(death) => death.message(),
+            GameOver::Winner(i) => WIN_MESSAGES[i],
+            GameOver::Draw => "Draw! Nobody survived...",
+            GameOver::TimeUp => "Time's up!",
+        }
+    }
 }
 
 #[derive(Debug)]
-pub struct Snake {
+pub struct Player {
     pub head: Point,
-    pub body: Vec<Point>,
-    pub egg: Point,
+    /// Tail at the front, neck at the back.
+    pub body: VecDeque<Point>,
     pub direction: Direction,
+    pub score: u32,
+    pub eggs: u32,
+    pub effect: Option<(FoodKind, u32)>,
+    pub death: Option<Death>,
+    turns: VecDeque<Direction>,
+}
+
+impl Player {
+    fn new(head: Point, direction: Direction) -> Player {
+        Player {
+            head: head,
+            body: VecDeque::new(),
+            direction: direction,
+            score: 0,
+            eggs: 0,
+            effect: None,
+            death: None,
+            turns: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.body.len() + 1
+    }
+
+    pub fn alive(&self) -> bool {
+        self.death.is_none()
+    }
+
+    /// Buffers a turn, one buffered turn is consumed per move.
+    pub fn turn(&mut self, direction: Direction) {
+        let last = *self.turns.back().unwrap_or(&self.direction);
+        if self.turns.len() < MAX_TURNS && direction != last && direction != last.opposite() {
+            self.turns.push_back(direction);
+        }
+    }
+
+    // Replaces the buffered turns with one towards `direction`.
+    fn steer(&mut self, direction: Direction) {
+        self.turns.clear();
+        self.turn(direction);
+    }
+
+    fn apply_turn(&mut self) {
+        // checked again against the direction the snake actually moved last
+        while let Some(direction) = self.turns.pop_front() {
+            if direction != self.direction && direction != self.direction.opposite() {
+                self.direction = direction;
+                break;
+            }
+        }
+    }
+
+    fn moves_this_tick(&self, ticks: u64) -> usize {
+        match self.effect {
+            _ if !self.alive() => 0,
+            Some((FoodKind::Faster, _)) => 2,
+            Some((FoodKind::Slower, _)) if ticks % 2 == 0 => 0,
+            _ => 1,
+        }
+    }
+}
+
+const NOT_FREE: usize = ::std::usize::MAX;
+
+// How many things (snake segments, walls, the egg and items) are on every
+// cell. The empty cells are also kept in a list, with `slots` pointing into
+// it, so that a random one can be picked and updated in O(1).
+#[derive(Debug)]
+struct Occupancy {
+    counts: Grid<u16>,
+    free: Vec<usize>,
+    slots: Vec<usize>,
+}
+
+impl Occupancy {
+    fn new(width: u32, height: u32) -> Occupancy {
+        let size = (width * height) as usize;
+        Occupancy {
+            counts: Grid::new(width, height, 0),
+            free: (0..size).collect(),
+            slots: (0..size).collect(),
+        }
+    }
+
+    fn count(&self, p: Point) -> u16 {
+        self.counts.get(p).cloned().unwrap_or(0)
+    }
+
+    fn add(&mut self, p: Point) {
+        if let Some(i) = self.counts.index(p) {
+            let count = self.counts.get_mut(p).unwrap();
+            *count += 1;
+            if *count == 1 {
+                let slot = self.slots[i];
+                let last = *self.free.last().unwrap();
+                self.free.swap_remove(slot);
+                if last != i {
+                    self.slots[last] = slot;
+                }
+                self.slots[i] = NOT_FREE;
+            }
+        }
+    }
+
+    fn remove(&mut self, p: Point) {
+        if let Some(i) = self.counts.index(p) {
+            let count = self.counts.get_mut(p).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                self.slots[i] = self.free.len();
+                self.free.push(i);
+            }
+        }
+    }
+
+    fn random_free<R: Rng>(&self, rng: &mut R) -> Option<Point> {
+        if self.free.is_empty() {
+            None
+        } else {
+            Some(self.counts.point(self.free[rng.gen_range(0, self.free.len())]))
+        }
+    }
+}
+
+/// The arena, holding one snake per player around a shared egg.
+#[derive(Debug)]
+pub struct Snake {
+    pub players: Vec<Player>,
+    pub egg: Point,
+    pub foods: Vec<Food>,
     pub width: u32,
     pub height: u32,
+    pub boundary: Boundary,
+    pub walls: Vec<Point>,
+    pub portals: Vec<(Point, Point)>,
+    pub target_length: Option<usize>,
+    pub mode: Mode,
+    pace: Pace,
+    occupancy: Occupancy,
+    wall_cells: Grid<bool>,
+    portal_exits: Grid<Option<Point>>,
+    full: bool,
+    ticks: u64,
+    elapsed: f64,
+    // rings of walls closed in so far in `Survival`, and the cells of the
+    // last one still waiting for a snake to move out
+    rings: i64,
+    closing: Vec<Point>,
+    rng: XorShiftRng,
+}
+
+fn spawn_point(width: u32, height: u32, player: usize) -> (Point, Direction) {
+    let (w, h) = (width as i64, height as i64);
+    match player % MAX_PLAYERS {
+        0 => (Point(w / 3, h / 3), Direction::Down),
+        1 => (Point(w - 1 - w / 3, h - 1 - h / 3), Direction::Up),
+        2 => (Point(w - 1 - w / 3, h / 3), Direction::Left),
+        _ => (Point(w / 3, h - 1 - h / 3), Direction::Right),
+    }
 }
 
 impl Snake {
     pub fn new_with_bounds(width: u32, height: u32) -> Snake {
-        Snake {
-            head: Point(10, 10),
-            body: Vec::new(),
-            egg: Point((width as i64) / 2, (height as i64) / 2),
-            direction: Direction::Down,
+        Snake::new_with_rules(width, height, Rules::default())
+    }
+
+    pub fn new_with_rules(width: u32, height: u32, rules: Rules) -> Snake {
+        Snake::new_with_players(width, height, 1, rules)
+    }
+
+    pub fn new_with_players(width: u32, height: u32, players: usize, rules: Rules) -> Snake {
+        let spawns = (0..players.min(MAX_PLAYERS))
+            .map(|i| spawn_point(width, height, i))
+            .collect::<Vec<_>>();
+        Snake::new_with_spawns(width, height, rules, &spawns, &[], &[])
+    }
+
+    pub fn new_with_level(level: &Level, rules: Rules) -> Snake {
+        let mut snake = Snake::new_with_spawns(level.width,
+                                               level.height,
+                                               rules,
+                                               &[(level.spawn, level.direction)],
+                                               &level.walls,
+                                               &level.portals);
+        snake.target_length = Some(level.target_length);
+        snake
+    }
+
+    fn new_with_spawns(width: u32,
+                       height: u32,
+                       rules: Rules,
+                       spawns: &[(Point, Direction)],
+                       walls: &[Point],
+                       portals: &[(Point, Point)])
+                       -> Snake {
+        // nothing kills a snake in `Zen`, so it can't be let out of the arena either
+        let boundary = if rules.mode == Mode::Zen { Boundary::Wrap } else { rules.boundary };
+        let mut snake = Snake {
+            players: spawns.iter().map(|&(head, direction)| Player::new(head, direction)).collect(),
+            // placed by `respawn_egg` once the players are in
+            egg: Point(-1, -1),
+            foods: Vec::new(),
             width: width,
             height: height,
+            boundary: boundary,
+            walls: walls.to_vec(),
+            portals: portals.to_vec(),
+            target_length: None,
+            mode: rules.mode,
+            pace: rules.pace,
+            occupancy: Occupancy::new(width, height),
+            wall_cells: Grid::new(width, height, false),
+            portal_exits: Grid::new(width, height, None),
+            full: false,
+            ticks: 0,
+            elapsed: 0.0,
+            rings: 0,
+            closing: Vec::new(),
+            rng: rand::thread_rng().gen(),
+        };
+        for &p in walls {
+            snake.wall_cells.set(p, true);
+        }
+        for &(a, b) in portals {
+            snake.portal_exits.set(a, Some(b));
+            snake.portal_exits.set(b, Some(a));
+        }
+        snake.reset_occupancy();
+        snake.respawn_egg();
+        snake
+    }
+
+    /// Makes food placement repeatable, for benchmarks and replays. Called
+    /// before the first move, it gives the same game for the same seed.
+    pub fn seed(&mut self, seed: u32) {
+        self.rng = XorShiftRng::from_seed([seed, 0x193a_6754, 0xa8a7_d469, 0x9783_0e05]);
+        // the order of the free cells depends on what was placed before
+        self.foods.clear();
+        self.reset_occupancy();
+        self.respawn_egg();
+    }
+
+    // Counts the walls, portals and snakes again on an otherwise empty board.
+    fn reset_occupancy(&mut self) {
+        self.occupancy = Occupancy::new(self.width, self.height);
+        for &p in &self.walls {
+            self.occupancy.add(p);
+        }
+        // portal cells stay taken so that no food lands on them
+        for &(a, b) in &self.portals {
+            self.occupancy.add(a);
+            self.occupancy.add(b);
+        }
+        for player in self.players.iter().filter(|p| p.alive()) {
+            self.occupancy.add(player.head);
+            for &p in &player.body {
+                self.occupancy.add(p);
+            }
+        }
+    }
+
+    pub fn level_complete(&self) -> bool {
+        self.target_length.map_or(false, |target| {
+            self.players.iter().any(|p| p.alive() && p.len() >= target)
+        })
+    }
+
+    /// Speed level from 1 to `MAX_SPEED`, going up as the longest snake grows.
+    pub fn speed(&self) -> u32 {
+        let longest = self.players.iter().filter(|p| p.alive()).map(|p| p.len()).max().unwrap_or(1);
+        (1 + (longest - 1) / SEGMENTS_PER_SPEED).min(MAX_SPEED as usize) as u32
+    }
+
+    /// Seconds between two `move_on` calls at the current speed.
+    pub fn move_interval(&self) -> f64 {
+        self.pace.interval_secs * SPEED_FACTOR.powi(self.speed() as i32 - 1)
+    }
+
+    /// Seconds of play so far, summed up from `move_interval`.
+    pub fn elapsed(&self) -> f64 {
+        self.elapsed
+    }
+
+    /// Seconds left in `TimeAttack`.
+    pub fn time_left(&self) -> Option<f64> {
+        match self.mode {
+            Mode::TimeAttack => Some((TIME_ATTACK_SECS - self.elapsed).max(0.0)),
+            _ => None,
         }
     }
 
-    pub fn move_on(&mut self) -> Result<(), &'static str> {
-        if self.next_head() == self.egg {
-            self.grow_once();
+    /// Width and height inside the rings closed in so far.
+    pub fn arena_size(&self) -> (u32, u32) {
+        (self.width - 2 * self.rings as u32, self.height - 2 * self.rings as u32)
+    }
+
+    pub fn turn(&mut self, player: usize, direction: Direction) {
+        if let Some(p) = self.players.get_mut(player) {
+            p.turn(direction);
+        }
+    }
+
+    /// The only player left alive, if there is exactly one.
+    pub fn winner(&self) -> Option<usize> {
+        let alive = self.players.iter().filter(|p| p.alive()).count();
+        if alive == 1 {
+            self.players.iter().position(|p| p.alive())
         } else {
-            self.move_once();
+            None
+        }
+    }
+
+    pub fn move_on(&mut self) -> Result<(), GameOver> {
+        self.move_on_with(|_, _| None)
+    }
+
+    /// Like `move_on`, asking `steer` for the direction of every player right
+    /// before each of its moves, so that computer players keep up with a
+    /// snake moving twice in a tick. `None` keeps the buffered turns.
+    pub fn move_on_with<F>(&mut self, mut steer: F) -> Result<(), GameOver>
+        where F: FnMut(&Snake, usize) -> Option<Direction>
+    {
+        self.ticks += 1;
+        self.elapsed += self.move_interval();
+        self.update_foods();
+
+        let moves = self.players.iter().map(|p| p.moves_this_tick(self.ticks)).collect::<Vec<_>>();
+        for sub in 0..2 {
+            if self.full {
+                break;
+            }
+            // a snake dying on its first move doesn't get a second one
+            let movers = (0..self.players.len())
+                .filter(|&i| moves[i] > sub && self.players[i].alive())
+                .collect::<Vec<_>>();
+            let directions = movers.iter().map(|&i| steer(self, i)).collect::<Vec<_>>();
+            for (&i, direction) in movers.iter().zip(directions) {
+                if let Some(direction) = direction {
+                    self.players[i].steer(direction);
+                }
+            }
+            if !movers.is_empty() {
+                self.step(&movers);
+            }
+        }
+
+        // built after the moves so that the walls are seen before they block
+        if self.mode == Mode::Survival && self.elapsed >= (self.rings + 1) as f64 * SURVIVAL_SECS {
+            self.close_in();
+        }
+        self.build_walls();
+
+        self.outcome()
+    }
+
+    fn outcome(&self) -> Result<(), GameOver> {
+        let time_up = self.time_left() == Some(0.0);
+        if self.players.len() == 1 {
+            match self.players[0].death {
+                Some(death) => Err(GameOver::Died(death)),
+                None if self.full => Err(GameOver::BoardFull),
+                None if time_up => Err(GameOver::TimeUp),
+                None => Ok(()),
+            }
+        } else if self.players.iter().filter(|p| p.alive()).count() > 1 {
+            if self.full {
+                Err(GameOver::BoardFull)
+            } else if time_up {
+                Err(GameOver::TimeUp)
+            } else {
+                Ok(())
+            }
+        } else {
+            match self.winner() {
+                Some(i) => Err(GameOver::Winner(i)),
+                None => Err(GameOver::Draw),
+            }
+        }
+    }
+
+    // All movers advance at once, collisions are only checked afterwards so
+    // the result does not depend on the order of the players.
+    fn step(&mut self, movers: &[usize]) {
+        let mut nexts = Vec::new();
+        for &i in movers {
+            self.players[i].apply_turn();
+            let next = self.next_head(&self.players[i]);
+            nexts.push((i, next));
         }
 
-        if self.hit_tail() {
-            Err("HitTail...")
-        } else if self.hit_wall() {
-            Err("HitWall...")
+        let mut egg_eaten = false;
+        for &(i, next) in &nexts {
+            // nobody eats a cell two heads are entering, they both die there
+            let contested = nexts.iter().filter(|&&(_, n)| n == next).count() > 1;
+            if !contested && next == self.egg {
+                self.occupancy.remove(next);
+                self.grow_once(i);
+                egg_eaten = true;
+            } else if let Some(f) = self.foods.iter().position(|f| !contested && f.pos == next) {
+                let food = self.foods.swap_remove(f);
+                self.occupancy.remove(next);
+                self.eat(i, food);
+            } else {
+                self.move_once(i, next);
+            }
+        }
+
+        // respawned only once everybody has moved, so it never lands under a head
+        if egg_eaten {
+            self.respawn_egg();
+            self.spawn_food();
+        }
+
+        let deaths = movers.iter()
+            .filter_map(|&i| self.collision(i).map(|death| (i, death)))
+            .collect::<Vec<_>>();
+        for (i, death) in deaths {
+            self.kill(i, death);
+        }
+    }
+
+    fn kill(&mut self, i: usize, death: Death) {
+        self.players[i].death = Some(death);
+        self.occupancy.remove(self.players[i].head);
+        if self.players.len() > 1 {
+            // the body stays on the board as eggs for the survivors
+            for &p in &self.players[i].body {
+                self.foods.push(Food {
+                    kind: FoodKind::Egg,
+                    pos: p,
+                    ticks_left: Some(CORPSE_TICKS),
+                });
+            }
         } else {
-            Ok(())
+            for &p in &self.players[i].body {
+                self.occupancy.remove(p);
+            }
         }
     }
 
-    fn hit_tail(&self) -> bool {
-        self.body.len() > 3 && self.body.contains(&self.head)
+    // Queues the walls of the next ring in from the border.
+    fn close_in(&mut self) {
+        let (w, h, r) = (self.width as i64, self.height as i64, self.rings);
+        if w - 2 * (r + 1) < MIN_ARENA || h - 2 * (r + 1) < MIN_ARENA {
+            return;
+        }
+        self.rings += 1;
+        self.closing.extend((r..w - r).flat_map(|x| vec![Point(x, r), Point(x, h - 1 - r)]));
+        self.closing.extend((r + 1..h - 1 - r).flat_map(|y| vec![Point(r, y), Point(w - 1 - r, y)]));
     }
 
-    fn hit_wall(&self) -> bool {
-        let Point(x, y) = self.head;
-        x < 0 || y < 0 || x >= (self.width as i64) || y >= (self.height as i64)
+    // Walls every queued cell no snake is on, taking over eggs and items.
+    fn build_walls(&mut self) {
+        let mut egg_lost = false;
+        for p in ::std::mem::replace(&mut self.closing, Vec::new()) {
+            if self.snake_on(p) {
+                self.closing.push(p);
+                continue;
+            }
+            self.wall_cells.set(p, true);
+            self.walls.push(p);
+            self.occupancy.add(p);
+            if let Some(f) = self.foods.iter().position(|f| f.pos == p) {
+                self.foods.swap_remove(f);
+                self.occupancy.remove(p);
+            }
+            if self.egg == p {
+                self.occupancy.remove(p);
+                egg_lost = true;
+            }
+        }
+        if egg_lost {
+            self.respawn_egg();
+        }
     }
 
-    fn next_head(&mut self) -> Point {
-        match self.direction {
-            Direction::Down => self.head + Point(0, 1),
-            Direction::Up => self.head + Point(0, -1),
-            Direction::Left => self.head + Point(-1, 0),
-            Direction::Right => self.head + Point(1, 0),
+    // Whether part of a snake is on `p`, that is, whether the cell is counted
+    // more often than the walls, portals, egg and items on it account for.
+    fn snake_on(&self, p: Point) -> bool {
+        let others = self.wall_cells.get(p).map_or(0, |&w| w as usize) +
+                     self.portal_exit(p).is_some() as usize +
+                     (self.egg == p) as usize +
+                     self.foods.iter().filter(|f| f.pos == p).count();
+        self.occupancy.count(p) as usize > others
+    }
+
+    // Only a crowded head cell needs a closer look at what is in there.
+    fn collision(&self, i: usize) -> Option<Death> {
+        let head = self.players[i].head;
+        let portal = self.portal_exit(head).is_some() as u16;
+        if self.mode == Mode::Zen {
+            None
+        } else if self.hit_wall(head) {
+            Some(Death::HitWall)
+        } else if self.occupancy.count(head) - portal <= 1 {
+            None
+        } else if self.wall_cells.get(head) == Some(&true) {
+            Some(Death::HitObstacle)
+        } else if self.players.iter().enumerate().any(|(j, p)| j != i && p.alive() && p.head == head) {
+            Some(Death::HitHead)
+        } else if self.hit_tail(&self.players[i]) {
+            Some(Death::HitTail)
+        } else {
+            Some(Death::HitSnake)
         }
     }
 
-    fn next_egg(&self) -> Point {
-        let mut new_egg: Point;
-        let mut x: u32;
-        let mut y: u32;
+    // The tail has already left its cell when the head moves in, so
+    // following it closely is fine.
+    fn hit_tail(&self, player: &Player) -> bool {
+        player.body.contains(&player.head)
+    }
+
+    fn hit_wall(&self, head: Point) -> bool {
+        self.boundary == Boundary::Walls && !self.in_bounds(head)
+    }
+
+    fn next_head(&self, player: &Player) -> Point {
+        self.step_from(player.head, player.direction)
+    }
+
+    /// The cell reached by moving one step from `p`, wrapped around in `Wrap`
+    /// arenas. Stepping into a portal comes out on the other end of the pair.
+    pub fn step_from(&self, p: Point, direction: Direction) -> Point {
+        let next = match self.boundary {
+            Boundary::Walls => p + direction.delta(),
+            Boundary::Wrap => self.wrap(p + direction.delta()),
+        };
+        self.portal_exit(next).unwrap_or(next)
+    }
+
+    pub fn portal_exit(&self, p: Point) -> Option<Point> {
+        self.portal_exits.get(p).cloned().unwrap_or(None)
+    }
+
+    pub fn in_bounds(&self, p: Point) -> bool {
+        Rect::sized(self.width, self.height).contains(p)
+    }
+
+    fn wrap(&self, p: Point) -> Point {
+        Rect::sized(self.width, self.height).wrap(p)
+    }
 
-        loop {
-            x = rand::thread_rng().gen_range(0, self.width);
-            y = rand::thread_rng().gen_range(0, self.height);
-            new_egg = Point(x as i64, y as i64);
-            if new_egg != self.head && !self.body.contains(&new_egg) {
-                return new_egg;
+    fn next_egg(&mut self) -> Option<Point> {
+        self.occupancy.random_free(&mut self.rng)
+    }
+
+    fn respawn_egg(&mut self) {
+        match self.next_egg() {
+            Some(p) => self.egg = p,
+            // the last free cell may hold an item, the egg takes it over
+            None => match self.foods.pop() {
+                Some(food) => {
+                    self.occupancy.remove(food.pos);
+                    self.egg = food.pos;
+                }
+                None => {
+                    self.full = true;
+                    return;
+                }
+            },
+        }
+        self.occupancy.add(self.egg);
+    }
+
+    fn grow_once(&mut self, i: usize) {
+        let points = FoodKind::Egg.points() * self.speed();
+        let egg = self.egg;
+        self.grow_to(i, egg);
+        self.players[i].score += points;
+        self.players[i].eggs += 1;
+    }
+
+    fn grow_to(&mut self, i: usize, next: Point) {
+        let player = &mut self.players[i];
+        player.body.push_back(player.head);
+        player.head = next;
+        self.occupancy.add(next);
+    }
+
+    fn shrink(&mut self, i: usize, n: usize) {
+        for _ in 0..n {
+            match self.players[i].body.pop_front() {
+                Some(tail) => self.occupancy.remove(tail),
+                None => break,
             }
         }
     }
 
-    fn grow_once(&mut self) {
-        self.body.push(self.head);
-        self.head = self.egg;
+    fn eat(&mut self, i: usize, food: Food) {
+        let points = food.kind.points() * self.speed();
+        self.players[i].score += points;
+        match food.kind {
+            FoodKind::Egg => {
+                self.grow_to(i, food.pos);
+                self.players[i].eggs += 1;
+            }
+            FoodKind::Bonus => self.grow_to(i, food.pos),
+            FoodKind::Poison => {
+                self.move_once(i, food.pos);
+                self.shrink(i, POISON_SHRINK);
+            }
+            FoodKind::Faster | FoodKind::Slower => {
+                self.move_once(i, food.pos);
+                self.players[i].effect = Some((food.kind, EFFECT_TICKS));
+            }
+        }
+    }
+
+    fn spawn_food(&mut self) {
+        let total = FOOD_KINDS.iter().map(|k| k.weight()).sum::<u32>();
+        let mut roll = self.rng.gen_range(0, total);
+        for kind in &FOOD_KINDS {
+            if roll < kind.weight() {
+                if *kind != FoodKind::Egg && !self.full {
+                    if let Some(pos) = self.next_egg() {
+                        let ticks_left = self.lifetime(*kind);
+                        self.occupancy.add(pos);
+                        self.foods.push(Food {
+                            kind: *kind,
+                            pos: pos,
+                            ticks_left: ticks_left,
+                        });
+                    }
+                }
+                return;
+            }
+            roll -= kind.weight();
+        }
+    }
 
-        self.egg = self.next_egg();
+    // The lifetime of the kind, stretched by the pace.
+    fn lifetime(&self, kind: FoodKind) -> Option<u32> {
+        kind.lifetime().map(|t| (t as f64 * self.pace.food_lifetime).round().max(1.0) as u32)
     }
 
-    fn move_once(&mut self) {
-        self.body.push(self.head);
-        self.head = self.next_head();
+    fn update_foods(&mut self) {
+        for food in &mut self.foods {
+            food.ticks_left = food.ticks_left.map(|t| t.saturating_sub(1));
+        }
+        for food in self.foods.iter().filter(|f| f.ticks_left == Some(0)) {
+            self.occupancy.remove(food.pos);
+        }
+        self.foods.retain(|f| f.ticks_left != Some(0));
+
+        for player in &mut self.players {
+            player.effect = match player.effect {
+                Some((kind, t)) if t > 1 => Some((kind, t - 1)),
+                _ => None,
+            };
+        }
+    }
+
+    fn move_once(&mut self, i: usize, next: Point) {
+        self.grow_to(i, next);
+        self.shrink(i, 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The egg and the items on the board after each of `ticks` moves in Zen,
+    // turning every few ticks so that the snake crosses the whole arena.
+    fn food_sequence(seeds: &[u32], ticks: u32) -> Vec<(Point, Vec<(FoodKind, Point)>)> {
+        let rules = Rules { mode: Mode::Zen, ..Rules::default() };
+        let mut snake = Snake::new_with_rules(12, 12, rules);
+        for &seed in seeds {
+            snake.seed(seed);
+        }
+        let mut sequence = Vec::new();
+        for t in 0..ticks {
+            if t % 7 == 0 {
+                let direction = if t % 14 == 0 { Direction::Right } else { Direction::Down };
+                snake.turn(0, direction);
+            }
+            assert_eq!(snake.move_on(), Ok(()));
+            sequence.push((snake.egg, snake.foods.iter().map(|f| (f.kind, f.pos)).collect()));
+        }
+        sequence
+    }
+
+    // An arena with a snake at each of `spawns`, no walls and no portals.
+    fn arena(spawns: &[(Point, Direction)]) -> Snake {
+        let mut snake = Snake::new_with_spawns(10, 10, Rules::default(), spawns, &[], &[]);
+        snake.seed(1);
+        snake
+    }
 
-        self.body.remove(0);
+    // Gives player `i` the body cells `body`, tail first.
+    fn set_body(snake: &mut Snake, i: usize, body: &[Point]) {
+        snake.players[i].body = body.iter().cloned().collect();
+        snake.foods.clear();
+        snake.reset_occupancy();
+        snake.respawn_egg();
+    }
+
+    #[test]
+    fn quick_turns_are_taken_one_per_tick() {
+        let mut snake = arena(&[(Point(5, 5), Direction::Right)]);
+        snake.turn(0, Direction::Up);
+        snake.turn(0, Direction::Left);
+        assert_eq!(snake.move_on(), Ok(()));
+        assert_eq!(snake.players[0].head, Point(5, 4));
+        assert_eq!(snake.players[0].direction, Direction::Up);
+        assert_eq!(snake.move_on(), Ok(()));
+        assert_eq!(snake.players[0].head, Point(4, 4));
+        assert_eq!(snake.players[0].direction, Direction::Left);
+    }
+
+    #[test]
+    fn heads_meeting_on_a_cell_both_die() {
+        let mut snake = arena(&[(Point(2, 5), Direction::Right), (Point(4, 5), Direction::Left)]);
+        assert_eq!(snake.move_on(), Err(GameOver::Draw));
+        assert_eq!(snake.players[0].death, Some(Death::HitHead));
+        assert_eq!(snake.players[1].death, Some(Death::HitHead));
+    }
+
+    #[test]
+    fn head_into_another_body_dies() {
+        let mut snake = arena(&[(Point(2, 5), Direction::Right), (Point(3, 4), Direction::Up)]);
+        set_body(&mut snake, 1, &[Point(3, 6), Point(3, 5)]);
+        assert_eq!(snake.move_on(), Err(GameOver::Winner(1)));
+        assert_eq!(snake.players[0].death, Some(Death::HitSnake));
+        assert!(snake.players[1].alive());
+    }
+
+    #[test]
+    fn head_may_follow_its_tail() {
+        let mut snake = arena(&[(Point(4, 5), Direction::Up)]);
+        set_body(&mut snake, 0, &[Point(4, 4), Point(5, 4), Point(5, 5)]);
+        for &direction in &[Direction::Up, Direction::Right, Direction::Down, Direction::Left] {
+            snake.turn(0, direction);
+            assert_eq!(snake.move_on(), Ok(()));
+        }
+        assert_eq!(snake.players[0].head, Point(4, 5));
+        assert_eq!(snake.players[0].len(), 4);
+
+        // any other part of the body is still in the way
+        set_body(&mut snake, 0, &[Point(3, 4), Point(4, 4), Point(5, 4), Point(5, 5)]);
+        snake.turn(0, Direction::Up);
+        assert_eq!(snake.move_on(), Err(GameOver::Died(Death::HitTail)));
+    }
+
+    #[test]
+    fn no_free_cell_left_wins() {
+        let spawns = [(Point(0, 0), Direction::Right)];
+        let mut snake = Snake::new_with_spawns(2, 1, Rules::default(), &spawns, &[], &[]);
+        assert_eq!(snake.egg, Point(1, 0));
+        assert_eq!(snake.move_on(), Err(GameOver::BoardFull));
+        assert_eq!(snake.players[0].len(), 2);
+    }
+
+    #[test]
+    fn spawn_point_follows_the_arena_size() {
+        for &(width, height) in &[(4, 4), (9, 9), (30, 20)] {
+            let snake = Snake::new_with_players(width, height, 2, Rules::default());
+            let (w, h) = (width as i64, height as i64);
+            assert_eq!(snake.players[0].head, Point(w / 3, h / 3));
+            assert_eq!(snake.players[1].head, Point(w - 1 - w / 3, h - 1 - h / 3));
+            assert!(snake.players.iter().all(|p| snake.in_bounds(p.head)));
+        }
+    }
+
+    #[test]
+    fn occupancy_keeps_free_cells_listed() {
+        let mut occupancy = Occupancy::new(5, 4);
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut added = Vec::new();
+        for _ in 0..2000 {
+            if added.is_empty() || rng.gen_range(0, 3) != 0 {
+                // off the board sometimes, which is ignored
+                let p = Point(rng.gen_range(-1, 6), rng.gen_range(-1, 5));
+                occupancy.add(p);
+                if occupancy.counts.contains(p) {
+                    added.push(p);
+                }
+            } else {
+                let i = rng.gen_range(0, added.len());
+                occupancy.remove(added.swap_remove(i));
+            }
+
+            let mut free = 0;
+            for i in 0..20 {
+                let p = occupancy.counts.point(i);
+                assert_eq!(occupancy.count(p) as usize, added.iter().filter(|&&a| a == p).count());
+                if occupancy.count(p) == 0 {
+                    assert_eq!(occupancy.free[occupancy.slots[i]], i);
+                    free += 1;
+                } else {
+                    assert_eq!(occupancy.slots[i], NOT_FREE);
+                }
+            }
+            assert_eq!(occupancy.free.len(), free);
+        }
+    }
+
+    #[test]
+    fn portal_leads_to_its_other_end() {
+        let portals = [(Point(3, 5), Point(7, 2))];
+        let spawns = [(Point(1, 5), Direction::Right)];
+        let mut snake = Snake::new_with_spawns(10, 10, Rules::default(), &spawns, &[], &portals);
+        snake.seed(1);
+        assert_eq!(snake.portal_exit(Point(3, 5)), Some(Point(7, 2)));
+        assert_eq!(snake.portal_exit(Point(7, 2)), Some(Point(3, 5)));
+        assert_eq!(snake.step_from(Point(2, 5), Direction::Right), Point(7, 2));
+
+        for head in &[Point(2, 5), Point(7, 2), Point(8, 2)] {
+            assert_eq!(snake.move_on(), Ok(()));
+            assert_eq!(snake.players[0].head, *head);
+        }
+    }
+
+    #[test]
+    fn survival_stops_closing_in_at_the_smallest_arena() {
+        let rules = Rules { mode: Mode::Survival, ..Rules::default() };
+        let mut snake = Snake::new_with_rules(20, 20, rules);
+        for _ in 0..10 {
+            snake.close_in();
+        }
+        assert_eq!(snake.arena_size(), (MIN_ARENA as u32, MIN_ARENA as u32));
+
+        // everything but the middle is walled once the snake is out of the way
+        snake.build_walls();
+        let middle = Rect::new(6, 6, MIN_ARENA, MIN_ARENA);
+        assert!(snake.closing.is_empty());
+        assert_eq!(snake.walls.len(), 20 * 20 - 8 * 8);
+        assert!(snake.walls.iter().all(|p| !middle.contains(*p)));
+    }
+
+    #[test]
+    fn same_seed_same_game() {
+        for seed in 1..100 {
+            let game = food_sequence(&[seed], 300);
+            assert_eq!(food_sequence(&[seed], 300), game);
+            // nothing placed before seeding changes the game
+            assert_eq!(food_sequence(&[seed + 100, seed], 300), game);
+        }
+        assert!(food_sequence(&[1], 300) != food_sequence(&[2], 300));
     }
 }
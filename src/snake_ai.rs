@@ -0,0 +1,390 @@
+use rand::{self, Rng};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+
+use geometry::{Grid, DIRECTIONS};
+use snake::{Boundary, Direction, FoodKind, Point, Snake};
+
+// Cells a move can't enter.
+struct Board {
+    blocked: Grid<bool>,
+}
+
+impl Board {
+    // Walls and the other snakes, the own snake is added by the caller.
+    fn new(snake: &Snake, player: usize) -> Board {
+        let mut board = Board { blocked: Grid::new(snake.width, snake.height, false) };
+        for p in &snake.walls {
+            board.block(*p);
+        }
+        for (i, other) in snake.players.iter().enumerate() {
+            if i != player && other.alive() {
+                board.block(other.head);
+                for p in &other.body {
+                    board.block(*p);
+                }
+            }
+        }
+        board
+    }
+
+    fn block(&mut self, p: Point) {
+        self.blocked.set(p, true);
+    }
+
+    fn unblock(&mut self, p: Point) {
+        self.blocked.set(p, false);
+    }
+
+    fn free(&self, p: Point) -> bool {
+        self.blocked.get(p) == Some(&false)
+    }
+
+    // A grid the size of the board filled with `value`.
+    fn grid<T: Clone>(&self, value: T) -> Grid<T> {
+        let bounds = self.blocked.bounds();
+        Grid::new(bounds.width as u32, bounds.height as u32, value)
+    }
+
+    // What `player` can move into on the next tick.
+    fn around(snake: &Snake, player: usize) -> Board {
+        let me = &snake.players[player];
+        let mut board = Board::new(snake, player);
+        board.block(me.head);
+        for p in &me.body {
+            board.block(*p);
+        }
+        // the tail moves out of the way on the next tick, unless it is the neck
+        if me.body.len() > 1 {
+            board.unblock(me.body[0]);
+        }
+        board.block(snake.step_from(me.head, me.direction.opposite()));
+        // only eggs are safe to eat, the other items shrink or speed the snake up
+        for food in snake.foods.iter().filter(|f| f.kind != FoodKind::Egg) {
+            board.block(food.pos);
+        }
+        board
+    }
+}
+
+// The cells one move away from `p`, just its neighbours on the board unless
+// the arena wraps or has portals.
+fn moves_from(snake: &Snake, board: &Board, p: Point) -> Vec<Point> {
+    if snake.boundary == Boundary::Walls && snake.portals.is_empty() {
+        board.blocked.neighbors(p)
+    } else {
+        DIRECTIONS.iter().map(|d| snake.step_from(p, *d)).collect()
+    }
+}
+
+fn distance(snake: &Snake, a: Point, b: Point) -> usize {
+    let (dx, dy) = ((a.0 - b.0).abs(), (a.1 - b.1).abs());
+    match snake.boundary {
+        Boundary::Walls => (dx + dy) as usize,
+        Boundary::Wrap => {
+            (dx.min(snake.width as i64 - dx) + dy.min(snake.height as i64 - dy)) as usize
+        }
+    }
+}
+
+// Shortest path from `from` to `to`, excluding `from` and including `to`.
+fn a_star(snake: &Snake, board: &Board, from: Point, to: Point) -> Option<Vec<Point>> {
+    let mut came_from: Grid<Option<Point>> = board.grid(None);
+    let mut cost = board.grid(usize::max_value());
+    let mut open = BinaryHeap::new();
+
+    cost.set(from, 0);
+    open.push(Reverse((distance(snake, from, to), 0, from.0, from.1)));
+
+    while let Some(Reverse((_, g, x, y))) = open.pop() {
+        let p = Point(x, y);
+        if p == to {
+            let mut path = vec![to];
+            let mut p = to;
+            while let Some(&Some(prev)) = came_from.get(p) {
+                if prev == from {
+                    break;
+                }
+                path.push(prev);
+                p = prev;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        if g > cost.get(p).cloned().unwrap_or(0) {
+            continue;
+        }
+
+        for n in moves_from(snake, board, p) {
+            if !board.free(n) || (n == from) {
+                continue;
+            }
+            if cost.get(n).map_or(false, |&c| g + 1 < c) {
+                cost.set(n, g + 1);
+                came_from.set(n, Some(p));
+                open.push(Reverse((g + 1 + distance(snake, n, to), g + 1, n.0, n.1)));
+            }
+        }
+    }
+    None
+}
+
+fn flood_area(snake: &Snake, board: &Board, from: Point) -> usize {
+    let mut seen = board.grid(false);
+    let mut queue = VecDeque::new();
+    seen.set(from, true);
+    queue.push_back(from);
+
+    let mut area = 0;
+    while let Some(p) = queue.pop_front() {
+        area += 1;
+        for n in moves_from(snake, board, p) {
+            if board.free(n) && seen.get(n) == Some(&false) {
+                seen.set(n, true);
+                queue.push_back(n);
+            }
+        }
+    }
+    area
+}
+
+/// Picks a direction for one player of a `Snake` arena before each move.
+///
+/// Alone in an open arena it follows a Hamiltonian cycle, only cutting ahead
+/// when the body stays in cycle order from the tail to the head. The cycle
+/// is then always free to follow, so the snake fills the board. With walls,
+/// portals or other snakes around it takes the A* route to the egg when its
+/// own tail is still reachable once it gets there, and chases its tail
+/// otherwise.
+pub struct Autopilot {
+    player: usize,
+    // cells in cycle order, and the position of every cell in that order
+    cycle: Vec<Point>,
+    order: Grid<usize>,
+}
+
+impl Autopilot {
+    pub fn new(snake: &Snake, player: usize) -> Autopilot {
+        // the body has to follow the cycle from the first move on
+        let alone = snake.players.len() == 1;
+        let cycle = if alone && snake.walls.is_empty() && snake.portals.is_empty() {
+            hamiltonian_cycle(snake.width as i64, snake.height as i64)
+        } else {
+            Vec::new()
+        };
+        let mut order = Grid::new(snake.width, snake.height, 0);
+        for (i, p) in cycle.iter().enumerate() {
+            order.set(*p, i);
+        }
+
+        Autopilot {
+            player: player,
+            cycle: cycle,
+            order: order,
+        }
+    }
+
+    pub fn choose(&self, snake: &Snake) -> Direction {
+        // walls closing in break the cycle
+        if !self.cycle.is_empty() && snake.walls.is_empty() {
+            if let Some(direction) = self.follow_cycle(snake) {
+                return direction;
+            }
+        }
+
+        let me = &snake.players[self.player];
+        let board = Board::around(snake, self.player);
+        if let Some(path) = a_star(snake, &board, me.head, snake.egg) {
+            if self.tail_reachable(snake, &path, true) {
+                return self.direction_to(snake, me.head, path[0]).unwrap_or(me.direction);
+            }
+        }
+
+        // stall: the safe move leaving the most room, or just the most room
+        let mut best = None;
+        for d in &DIRECTIONS {
+            let n = snake.step_from(me.head, *d);
+            if !board.free(n) {
+                continue;
+            }
+            let safe = self.tail_reachable(snake, &[n], false);
+            let area = flood_area(snake, &board, n);
+            if best.map_or(true, |(s, a, _)| (safe, area) > (s, a)) {
+                best = Some((safe, area, *d));
+            }
+        }
+        best.map_or(me.direction, |(_, _, d)| d)
+    }
+
+    // Cells from `from` forward to `to` along the cycle.
+    fn ahead(&self, from: Point, to: Point) -> usize {
+        let order = |p| self.order.get(p).cloned().unwrap_or(0);
+        (order(to) + self.cycle.len() - order(from)) % self.cycle.len()
+    }
+
+    // The next cell on the cycle, or the one furthest along it short of the
+    // egg that is still before the tail. Everything from the head on to the
+    // tail is empty, so the body never has to cross itself.
+    fn follow_cycle(&self, snake: &Snake) -> Option<Direction> {
+        let me = &snake.players[self.player];
+        let room = match me.body.front() {
+            Some(tail) => self.ahead(me.head, *tail),
+            None => self.cycle.len(),
+        };
+        let egg = self.ahead(me.head, snake.egg);
+        DIRECTIONS.iter()
+            .cloned()
+            .map(|d| (d, snake.step_from(me.head, d)))
+            .filter(|&(_, n)| snake.in_bounds(n))
+            .map(|(d, n)| (d, self.ahead(me.head, n)))
+            .filter(|&(_, k)| k == 1 || (k > 1 && k < room && k <= egg))
+            .max_by_key(|&(_, k)| k)
+            .map(|(d, _)| d)
+    }
+
+    fn direction_to(&self, snake: &Snake, from: Point, to: Point) -> Option<Direction> {
+        DIRECTIONS.iter().cloned().find(|d| snake.step_from(from, *d) == to)
+    }
+
+    // Moves a copy of the snake along `path` and checks it can still follow its tail.
+    fn tail_reachable(&self, snake: &Snake, path: &[Point], grows: bool) -> bool {
+        let me = &snake.players[self.player];
+        let mut body = me.body.iter().cloned().collect::<VecDeque<_>>();
+        body.push_back(me.head);
+        for (k, p) in path.iter().enumerate() {
+            body.push_back(*p);
+            if !(grows && k + 1 == path.len()) {
+                body.pop_front();
+            }
+        }
+        if body.len() < 3 {
+            return true;
+        }
+
+        let mut board = Board::new(snake, self.player);
+        for food in snake.foods.iter().filter(|f| f.kind != FoodKind::Egg) {
+            board.block(food.pos);
+        }
+        for p in &body {
+            board.block(*p);
+        }
+        let tail = body[0];
+        let head = body[body.len() - 1];
+        board.unblock(tail);
+        a_star(snake, &board, head, tail).is_some()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Difficulty {
+    RandomWalk,
+    Greedy,
+    PathPlanning,
+}
+
+/// A computer-controlled opponent.
+pub struct Bot {
+    player: usize,
+    difficulty: Difficulty,
+    autopilot: Autopilot,
+}
+
+impl Bot {
+    pub fn new(snake: &Snake, player: usize, difficulty: Difficulty) -> Bot {
+        Bot {
+            player: player,
+            difficulty: difficulty,
+            autopilot: Autopilot::new(snake, player),
+        }
+    }
+
+    pub fn player(&self) -> usize {
+        self.player
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
+
+    pub fn choose(&self, snake: &Snake) -> Direction {
+        let me = &snake.players[self.player];
+        let board = Board::around(snake, self.player);
+        let safe = DIRECTIONS.iter()
+            .cloned()
+            .filter(|d| board.free(snake.step_from(me.head, *d)))
+            .collect::<Vec<_>>();
+        if safe.is_empty() {
+            return me.direction;
+        }
+
+        match self.difficulty {
+            Difficulty::RandomWalk => {
+                let mut rng = rand::thread_rng();
+                if safe.contains(&me.direction) && rng.gen_range(0, 4) != 0 {
+                    me.direction
+                } else {
+                    safe[rng.gen_range(0, safe.len())]
+                }
+            }
+            Difficulty::Greedy => {
+                *safe.iter()
+                    .min_by_key(|d| distance(snake, snake.step_from(me.head, **d), snake.egg))
+                    .unwrap()
+            }
+            Difficulty::PathPlanning => self.autopilot.choose(snake),
+        }
+    }
+}
+
+// A cycle through every cell, going up the first column and snaking through
+// the rest. Needs an even number of rows or columns.
+fn hamiltonian_cycle(width: i64, height: i64) -> Vec<Point> {
+    if width < 2 || height < 2 {
+        return Vec::new();
+    }
+    if height % 2 != 0 {
+        if width % 2 != 0 {
+            return Vec::new();
+        }
+        return hamiltonian_cycle(height, width).iter().map(|p| Point(p.1, p.0)).collect();
+    }
+
+    let mut cycle = Vec::new();
+    for y in 0..height {
+        if y % 2 == 0 {
+            cycle.extend((1..width).map(|x| Point(x, y)));
+        } else {
+            cycle.extend((1..width).rev().map(|x| Point(x, y)));
+        }
+    }
+    cycle.extend((0..height).rev().map(|y| Point(0, y)));
+    cycle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use snake::{GameOver, Rules};
+
+    fn autopilot_game(width: u32, height: u32, boundary: Boundary, seed: u32) -> Result<(), GameOver> {
+        let rules = Rules { boundary: boundary, ..Rules::default() };
+        let mut snake = Snake::new_with_rules(width, height, rules);
+        snake.seed(seed);
+        let autopilot = Autopilot::new(&snake, 0);
+        for _ in 0..1_000_000 {
+            snake.move_on_with(|snake, _| Some(autopilot.choose(snake)))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn autopilot_fills_the_board() {
+        for seed in 1..21 {
+            assert_eq!(autopilot_game(10, 10, Boundary::Walls, seed), Err(GameOver::BoardFull));
+            assert_eq!(autopilot_game(10, 10, Boundary::Wrap, seed), Err(GameOver::BoardFull));
+        }
+        for seed in 1..4 {
+            assert_eq!(autopilot_game(30, 30, Boundary::Walls, seed), Err(GameOver::BoardFull));
+        }
+    }
+}
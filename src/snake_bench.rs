@@ -0,0 +1,47 @@
+extern crate piston_games;
+
+use std::env;
+
+use piston_games::snake::*;
+use piston_games::snake_ai::Autopilot;
+
+// enough for the autopilot to fill a 30x30 board
+const MAX_TICKS: u32 = 2_000_000;
+
+// usage: snake_bench [games] [width] [height]
+fn main() {
+    let args = env::args().skip(1).map(|a| a.parse().unwrap_or(0)).collect::<Vec<u32>>();
+    let games = *args.get(0).unwrap_or(&100);
+    let width = *args.get(1).unwrap_or(&30);
+    let height = *args.get(2).unwrap_or(&30);
+
+    let mut total = 0;
+    let mut full = 0;
+    for seed in 0..games {
+        let mut snake = Snake::new_with_bounds(width, height);
+        snake.seed(seed + 1);
+        let autopilot = Autopilot::new(&snake, 0);
+
+        for _ in 0..MAX_TICKS {
+            match snake.move_on_with(|snake, _| Some(autopilot.choose(snake))) {
+                Ok(_) => {}
+                Err(GameOver::BoardFull) => {
+                    full += 1;
+                    break;
+                }
+                Err(game_over) => {
+                    println!("seed {}: {} at length {}", seed + 1, game_over.message(), snake.players[0].len());
+                    break;
+                }
+            }
+        }
+        total += snake.players[0].len();
+    }
+
+    println!("{} games on {}x{}: average final length {:.1}, {} full boards",
+             games,
+             width,
+             height,
+             total as f64 / games.max(1) as f64,
+             full);
+}
@@ -0,0 +1,168 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use snake::{Direction, Point};
+
+/// A snake arena read from a text grid.
+///
+/// Lines of the form `key: value` set `direction` and the snake `length`
+/// that completes the level, which is required. Every other non-empty line
+/// is a row of the grid: `#` is a wall, `S` the spawn point, `.` a free cell
+/// and a lowercase letter one end of the portal pair marked by that letter.
+#[derive(Debug, Clone)]
+pub struct Level {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub walls: Vec<Point>,
+    pub portals: Vec<(Point, Point)>,
+    pub spawn: Point,
+    pub direction: Direction,
+    pub target_length: usize,
+}
+
+fn parse_direction(s: &str) -> Result<Direction, String> {
+    match s {
+        "Left" => Ok(Direction::Left),
+        "Right" => Ok(Direction::Right),
+        "Up" => Ok(Direction::Up),
+        "Down" => Ok(Direction::Down),
+        _ => Err(format!("unknown direction `{}`", s)),
+    }
+}
+
+impl Level {
+    pub fn load(path: &Path) -> Result<Level, String> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+        let name = path.file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+        Level::parse(&name, &contents).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    pub fn parse(name: &str, contents: &str) -> Result<Level, String> {
+        let mut direction = Direction::Right;
+        let mut target_length = None;
+        let mut rows = Vec::new();
+
+        for line in contents.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+            if let Some(i) = line.find(':') {
+                let value = line[i + 1..].trim();
+                match line[..i].trim() {
+                    "direction" => direction = parse_direction(value)?,
+                    "length" => {
+                        match value.parse() {
+                            Ok(length) if length > 0 => target_length = Some(length),
+                            _ => return Err(format!("bad length `{}`", value)),
+                        }
+                    }
+                    key => return Err(format!("unknown key `{}`", key)),
+                }
+            } else {
+                rows.push(line);
+            }
+        }
+
+        let width = rows.first().map_or(0, |r| r.len());
+        if width == 0 || rows.iter().any(|r| r.len() != width) {
+            return Err("grid rows must be non-empty and of equal width".to_string());
+        }
+
+        let mut walls = Vec::new();
+        let mut portal_ends: Vec<(char, Point)> = Vec::new();
+        let mut spawn = None;
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let p = Point(x as i64, y as i64);
+                match c {
+                    '#' => walls.push(p),
+                    'S' if spawn.is_none() => spawn = Some(p),
+                    'S' => return Err("more than one spawn point".to_string()),
+                    '.' => {}
+                    'a'..='z' => portal_ends.push((c, p)),
+                    _ => return Err(format!("unknown cell `{}`", c)),
+                }
+            }
+        }
+
+        let mut portals = Vec::new();
+        for (i, &(c, p)) in portal_ends.iter().enumerate() {
+            let ends = portal_ends.iter().filter(|&&(d, _)| d == c).collect::<Vec<_>>();
+            if ends.len() != 2 {
+                return Err(format!("portal `{}` needs exactly two ends", c));
+            }
+            // each pair is pushed once, from its first end
+            if !portal_ends[..i].iter().any(|&(d, _)| d == c) {
+                portals.push((p, ends[1].1));
+            }
+        }
+
+        Ok(Level {
+            name: name.to_string(),
+            width: width as u32,
+            height: rows.len() as u32,
+            walls: walls,
+            portals: portals,
+            spawn: spawn.ok_or("missing spawn point `S`")?,
+            direction: direction,
+            target_length: target_length.ok_or("missing length")?,
+        })
+    }
+
+    /// Loads every `*.txt` level in `dir`, ordered by file name.
+    pub fn load_dir(dir: &Path) -> Result<Vec<Level>, String> {
+        let mut paths = dir.read_dir()
+            .map_err(|err| format!("{}: {}", dir.display(), err))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| p.extension().map_or(false, |ext| ext == "txt"))
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths.iter().map(|p| Level::load(p)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_level() {
+        let level = Level::parse("test", "direction: Up\nlength: 6\n#a##\n.S.a\n#..#\n").unwrap();
+        assert_eq!((level.width, level.height), (4, 3));
+        assert_eq!(level.walls, vec![Point(0, 0), Point(2, 0), Point(3, 0), Point(0, 2), Point(3, 2)]);
+        assert_eq!(level.portals, vec![(Point(1, 0), Point(3, 1))]);
+        assert_eq!(level.spawn, Point(1, 1));
+        assert_eq!(level.direction, Direction::Up);
+        assert_eq!(level.target_length, 6);
+    }
+
+    #[test]
+    fn rows_of_different_width() {
+        assert_eq!(Level::parse("test", "length: 5\n..S\n..\n").unwrap_err(),
+                   "grid rows must be non-empty and of equal width");
+    }
+
+    #[test]
+    fn one_spawn_point() {
+        assert_eq!(Level::parse("test", "length: 5\n...\n...\n").unwrap_err(), "missing spawn point `S`");
+        assert_eq!(Level::parse("test", "length: 5\nS..\n..S\n").unwrap_err(), "more than one spawn point");
+    }
+
+    #[test]
+    fn portals_have_two_ends() {
+        assert_eq!(Level::parse("test", "length: 5\nS.a\n...\n").unwrap_err(),
+                   "portal `a` needs exactly two ends");
+        assert_eq!(Level::parse("test", "length: 5\nSaa\n.a.\n").unwrap_err(),
+                   "portal `a` needs exactly two ends");
+    }
+
+    #[test]
+    fn length_is_required_and_positive() {
+        assert_eq!(Level::parse("test", "S..\n...\n").unwrap_err(), "missing length");
+        assert_eq!(Level::parse("test", "length: 0\nS..\n").unwrap_err(), "bad length `0`");
+        assert_eq!(Level::parse("test", "length: -2\nS..\n").unwrap_err(), "bad length `-2`");
+    }
+}
@@ -1,81 +1,729 @@
 extern crate rand;
 
 use rand::Rng;
-use std::ops::{Add, AddAssign};
+use std::ops::{Add, Mul, Sub};
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
-pub struct Point(pub i64, pub i64);
+use geometry::Rect;
 
-impl Add<Point> for Point {
-    type Output = Point;
-    fn add(self, rhs: Point) -> Point {
-        Point(self.0 + rhs.0, self.1 + rhs.1)
+pub use geometry::{Direction, Point};
+
+/// A position or velocity in cells, the grid cells lying between whole numbers.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Vec2(pub f64, pub f64);
+
+impl Add<Vec2> for Vec2 {
+    type Output = Vec2;
+    fn add(self, rhs: Vec2) -> Vec2 {
+        Vec2(self.0 + rhs.0, self.1 + rhs.1)
+    }
+}
+
+impl Sub<Vec2> for Vec2 {
+    type Output = Vec2;
+    fn sub(self, rhs: Vec2) -> Vec2 {
+        Vec2(self.0 - rhs.0, self.1 - rhs.1)
+    }
+}
+
+impl Mul<f64> for Vec2 {
+    type Output = Vec2;
+    fn mul(self, rhs: f64) -> Vec2 {
+        Vec2(self.0 * rhs, self.1 * rhs)
+    }
+}
+
+impl Vec2 {
+    /// The grid cell this is in.
+    pub fn cell(&self) -> Point {
+        Point(self.0.floor() as i64, self.1.floor() as i64)
+    }
+
+    /// `t` of the way from here to `to`.
+    pub fn lerp(&self, to: Vec2, t: f64) -> Vec2 {
+        *self + (to - *self) * t
+    }
+}
+
+/// The middle of `cell`.
+pub fn center(cell: Point) -> Vec2 {
+    Vec2(cell.0 as f64 + 0.5, cell.1 as f64 + 0.5)
+}
+
+/// An axis-aligned hitbox.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Aabb {
+    pub center: Vec2,
+    pub half: Vec2,
+}
+
+impl Aabb {
+    pub fn new(center: Vec2, half: Vec2) -> Aabb {
+        Aabb {
+            center: center,
+            half: half,
+        }
+    }
+
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        (self.center.0 - other.center.0).abs() < self.half.0 + other.half.0 &&
+        (self.center.1 - other.center.1).abs() < self.half.1 + other.half.1
+    }
+
+    /// The fraction of `motion` after which this box first touches `other`,
+    /// if it does by the end of it. Zero when they already overlap.
+    pub fn sweep(&self, motion: Vec2, other: &Aabb) -> Option<f64> {
+        let (mut enter, mut exit) = (0.0f64, 1.0f64);
+        let axes = [(self.center.0, motion.0, other.center.0, self.half.0 + other.half.0),
+                    (self.center.1, motion.1, other.center.1, self.half.1 + other.half.1)];
+        for &(from, delta, to, reach) in &axes {
+            // the offsets along this axis at which the boxes overlap
+            let (low, high) = (to - reach - from, to + reach - from);
+            if delta == 0.0 {
+                if low >= 0.0 || high <= 0.0 {
+                    return None;
+                }
+            } else if delta > 0.0 {
+                enter = enter.max(low / delta);
+                exit = exit.min(high / delta);
+            } else {
+                enter = enter.max(high / delta);
+                exit = exit.min(low / delta);
+            }
+        }
+        if enter < exit { Some(enter) } else { None }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AlienKind {
+    Squid,
+    Crab,
+    Octopus,
+}
+
+impl AlienKind {
+    pub fn points(&self) -> u32 {
+        match *self {
+            AlienKind::Squid => 30,
+            AlienKind::Crab => 20,
+            AlienKind::Octopus => 10,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Alien {
+    pub kind: AlienKind,
+    pub pos: Point,
+}
+
+impl Alien {
+    pub fn hitbox(&self) -> Aabb {
+        Aabb::new(center(self.pos), ALIEN_HALF)
     }
 }
 
-impl AddAssign<Point> for Point {
-    fn add_assign(&mut self, rhs: Point) {
-        self.0 += rhs.0;
-        self.1 += rhs.1;
+/// A shot of the player.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Bullet {
+    pub pos: Vec2,
+    pub vel: Vec2,
+    /// Goes on through the aliens it hits.
+    pub piercing: bool,
+    // where it was before the last update
+    prev: Vec2,
+}
+
+impl Bullet {
+    /// Fired straight up from `pos`.
+    pub fn new(pos: Vec2) -> Bullet {
+        Bullet {
+            pos: pos,
+            vel: Vec2(0.0, -BULLET_SPEED),
+            piercing: false,
+            prev: pos,
+        }
+    }
+
+    /// Fired straight up from the middle of `cell`.
+    pub fn at(cell: Point) -> Bullet {
+        Bullet::new(center(cell))
+    }
+
+    pub fn cell(&self) -> Point {
+        self.pos.cell()
+    }
+
+    pub fn hitbox(&self) -> Aabb {
+        Aabb::new(self.pos, BULLET_HALF)
+    }
+
+    /// Where to draw it, `alpha` of the way from the last update to this one.
+    pub fn render_pos(&self, alpha: f64) -> Vec2 {
+        self.prev.lerp(self.pos, alpha)
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub enum BodyDirection {
-    Left,
-    Right,
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EggKind {
+    Plunger,
+    Squiggly,
+    /// Fast, and dropped over the player.
+    Rolling,
 }
 
+const EGG_KINDS: [EggKind; 3] = [EggKind::Plunger, EggKind::Squiggly, EggKind::Rolling];
+
+impl EggKind {
+    fn weight(&self) -> u32 {
+        match *self {
+            EggKind::Plunger => 5,
+            EggKind::Squiggly => 3,
+            EggKind::Rolling => 2,
+        }
+    }
+
+    /// Cells moved every two ticks.
+    pub fn speed(&self) -> u32 {
+        match *self {
+            EggKind::Plunger => 2,
+            EggKind::Squiggly => 1,
+            EggKind::Rolling => 3,
+        }
+    }
+
+    pub fn velocity(&self) -> Vec2 {
+        Vec2(0.0, self.speed() as f64 / (2.0 * TICK_SECS))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Egg {
+    pub kind: EggKind,
+    pub pos: Vec2,
+    prev: Vec2,
+}
+
+impl Egg {
+    /// Dropped from the middle of `cell`.
+    pub fn at(kind: EggKind, cell: Point) -> Egg {
+        Egg {
+            kind: kind,
+            pos: center(cell),
+            prev: center(cell),
+        }
+    }
+
+    pub fn cell(&self) -> Point {
+        self.pos.cell()
+    }
+
+    pub fn hitbox(&self) -> Aabb {
+        Aabb::new(self.pos, EGG_HALF)
+    }
+
+    pub fn render_pos(&self, alpha: f64) -> Vec2 {
+        self.prev.lerp(self.pos, alpha)
+    }
+}
+
+/// What a difficulty sets in the game.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Settings {
+    pub lives: u32,
+    /// Whether the damage done to the bunkers carries over to the next wave.
+    pub bunkers_persist: bool,
+    /// Ticks between two alien shots on the first wave with a full
+    /// formation, one less every wave down to the minimum.
+    pub fire_ticks: u32,
+    /// Ticks between two steps of a full formation, down to one when few
+    /// are left.
+    pub march_ticks: u32,
+    pub ammo: Ammo,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            lives: 3,
+            bunkers_persist: false,
+            fire_ticks: 12,
+            march_ticks: 4,
+            ammo: Ammo::new(8, 1.5, 0.25, 3),
+        }
+    }
+}
+
+/// How the gun of the player is loaded.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Ammo {
+    /// Shots in a full magazine.
+    pub magazine: u32,
+    /// Seconds to load a fresh magazine.
+    pub reload_secs: f64,
+    /// Seconds between two shots.
+    pub cooldown_secs: f64,
+    /// Bullets of the player allowed on screen at once.
+    pub max_on_screen: usize,
+}
+
+impl Ammo {
+    pub fn new(magazine: u32, reload_secs: f64, cooldown_secs: f64, max_on_screen: usize) -> Ammo {
+        Ammo {
+            magazine: magazine,
+            reload_secs: reload_secs,
+            cooldown_secs: cooldown_secs,
+            max_on_screen: max_on_screen,
+        }
+    }
+}
+
+// an extra life every this many points, up to the maximum
+const EXTRA_LIFE_SCORE: u32 = 1500;
+const MAX_LIVES: u32 = 9;
+// ticks of the explosion with enemy fire frozen, then of the respawned
+// player being immune to eggs
+const RESPAWN_TICKS: u32 = 10;
+const INVULNERABLE_TICKS: u32 = 15;
+
+// cells of one bunker from its top left corner, and how far above the
+// player the bunkers stand
+const BUNKER_SHAPE: [Point; 6] = [Point(0, 0), Point(1, 0), Point(2, 0), Point(3, 0), Point(0, 1), Point(3, 1)];
+const BUNKER_WIDTH: i64 = 4;
+const BUNKER_COUNT: i64 = 4;
+const BUNKER_RISE: i64 = 4;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Ufo {
+    pub pos: Vec2,
+    vel: Vec2,
+    prev: Vec2,
+}
+
+impl Ufo {
+    fn at(cell: Point, vel: Vec2) -> Ufo {
+        Ufo {
+            pos: center(cell),
+            vel: vel,
+            prev: center(cell),
+        }
+    }
+
+    pub fn cell(&self) -> Point {
+        self.pos.cell()
+    }
+
+    pub fn hitbox(&self) -> Aabb {
+        Aabb::new(self.pos, UFO_HALF)
+    }
+
+    pub fn render_pos(&self, alpha: f64) -> Vec2 {
+        self.prev.lerp(self.pos, alpha)
+    }
+}
+
+// the UFO crosses the top row of the field now and then, worth the points
+// at the shot count like in the original table: 300 for the 23rd shot and
+// every 15th before or after it
+const UFO_ROW: i64 = 5;
+const UFO_POINTS: [u32; 15] = [100, 50, 50, 100, 150, 100, 100, 50, 300, 100, 100, 100, 50, 150, 100];
+const UFO_MIN_TICKS: u32 = 60;
+const UFO_MAX_TICKS: u32 = 150;
+// ticks the points of a destroyed UFO stay on show
+const UFO_SCORE_TICKS: u32 = 10;
+// awarded in full for hitting with every shot of a wave
+const ACCURACY_BONUS: u32 = 500;
+// ticks the last accuracy bonus stays on show
+const BONUS_TICKS: u32 = 15;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PowerUpKind {
+    /// Three bullets fanning out.
+    Spread,
+    /// A shorter wait between shots.
+    Rapid,
+    /// Bullets go through the aliens.
+    Piercing,
+    /// Eggs bounce off the player.
+    Shield,
+    /// A full magazine, at once.
+    Ammo,
+}
+
+pub const POWER_UP_KINDS: [PowerUpKind; 5] = [PowerUpKind::Spread,
+                                              PowerUpKind::Rapid,
+                                              PowerUpKind::Piercing,
+                                              PowerUpKind::Shield,
+                                              PowerUpKind::Ammo];
+
+impl PowerUpKind {
+    /// Ticks the effect lasts once collected, none for the ones used at once.
+    pub fn ticks(&self) -> u32 {
+        match *self {
+            PowerUpKind::Spread => 50,
+            PowerUpKind::Rapid => 50,
+            PowerUpKind::Piercing => 40,
+            PowerUpKind::Shield => 50,
+            PowerUpKind::Ammo => 0,
+        }
+    }
+}
+
+/// Dropped by a destroyed alien, collected by touching it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PowerUp {
+    pub kind: PowerUpKind,
+    pub pos: Vec2,
+    prev: Vec2,
+}
+
+impl PowerUp {
+    pub fn at(kind: PowerUpKind, cell: Point) -> PowerUp {
+        PowerUp {
+            kind: kind,
+            pos: center(cell),
+            prev: center(cell),
+        }
+    }
+
+    pub fn hitbox(&self) -> Aabb {
+        Aabb::new(self.pos, POWER_UP_HALF)
+    }
+
+    pub fn render_pos(&self, alpha: f64) -> Vec2 {
+        self.prev.lerp(self.pos, alpha)
+    }
+}
+
+// percent of the destroyed aliens dropping a power-up
+const POWER_UP_CHANCE: u32 = 8;
+// how many times shorter the wait between shots is with rapid fire
+const RAPID_FIRE: f64 = 3.0;
+// sideways speed of the outer bullets of a spread shot, in cells per second
+const SPREAD_SPEED: f64 = 2.5;
+
+/// Seconds of a tick: the formation, the firing and every timer run in
+/// ticks, while everything in flight moves every update.
+pub const TICK_SECS: f64 = 0.2;
+// speeds in cells per second
+const BULLET_SPEED: f64 = 10.0;
+const UFO_SPEED: f64 = 5.0;
+const HEAD_SPEED: f64 = 10.0;
+const POWER_UP_SPEED: f64 = 4.0;
+// half the size of the hitboxes, in cells
+const ALIEN_HALF: Vec2 = Vec2(0.45, 0.45);
+const HEAD_HALF: Vec2 = Vec2(0.45, 0.45);
+const BUNKER_HALF: Vec2 = Vec2(0.5, 0.5);
+const BULLET_HALF: Vec2 = Vec2(0.15, 0.3);
+const EGG_HALF: Vec2 = Vec2(0.2, 0.3);
+const UFO_HALF: Vec2 = Vec2(1.0, 0.45);
+const POWER_UP_HALF: Vec2 = Vec2(0.4, 0.4);
+
+// Something hit during an update, by index.
+#[derive(Debug, Clone, Copy)]
+enum Hit {
+    BulletEgg(usize, usize),
+    BulletAlien(usize, usize),
+    BulletUfo(usize),
+    BulletBunker(usize, usize),
+    EggBunker(usize, usize),
+    EggHead(usize),
+    PowerUpHead(usize),
+}
+
+const MIN_FIRE_TICKS: u32 = 3;
+const MAX_EGGS: usize = 6;
+
+// kind of every formation row from the top, and the cells between two aliens
+const FORMATION_ROWS: [AlienKind; 5] = [AlienKind::Squid,
+                                        AlienKind::Crab,
+                                        AlienKind::Crab,
+                                        AlienKind::Octopus,
+                                        AlienKind::Octopus];
+const FORMATION_COLUMNS: i64 = 8;
+const FORMATION_GAP: i64 = 2;
+const FORMATION_TOP: i64 = 7;
+// every wave starts a row lower, down to this many rows
+const MAX_WAVE_DROP: i64 = 4;
+
 #[derive(Debug)]
 pub struct SpaceInvaders {
+    /// The cell of the player, `head_pos` snapped to the grid.
     pub head: Point,
-    pub body: Vec<Point>,
-    pub bullets: Vec<Point>,
-    pub eggs: Vec<Point>,
-    pub body_direction: BodyDirection,
+    pub body: Vec<Alien>,
+    pub bullets: Vec<Bullet>,
+    pub eggs: Vec<Egg>,
+    pub bunkers: Vec<Point>,
+    pub body_direction: Direction,
     pub width: u32,
     pub height: u32,
-    max_bullets: u32,
+    pub score: u32,
+    pub wave: u32,
+    pub settings: Settings,
+    pub lives: u32,
+    pub ufo: Option<Ufo>,
+    pub power_ups: Vec<PowerUp>,
+    head_pos: Vec2,
+    head_prev: Vec2,
+    steering: Option<Direction>,
+    // seconds until the next tick
+    next_tick: f64,
+    // shots left in the magazine, seconds until the player can shoot again
+    // and until the magazine is loaded, if it is being loaded
+    rounds: u32,
+    shot_cooldown: f64,
+    reload_secs: f64,
+    // ticks left of every timed power-up effect in play
+    effects: Vec<(PowerUpKind, u32)>,
     count_bullets: u32,
+    formation_size: usize,
+    march_ticks: u32,
+    fire_ticks: u32,
+    respawn_ticks: u32,
+    invulnerable_ticks: u32,
+    next_extra_life: u32,
+    player_shot: bool,
+    ufo_ticks: u32,
+    ufo_score: Option<(Point, u32)>,
+    ufo_score_ticks: u32,
+    wave_shots: u32,
+    wave_hits: u32,
+    last_bonus: u32,
+    bonus_ticks: u32,
 }
 
 impl SpaceInvaders {
     pub fn new_with_bounds(width: u32, height: u32) -> SpaceInvaders {
-        SpaceInvaders {
+        SpaceInvaders::new_with_settings(width, height, Settings::default())
+    }
+
+    pub fn new_with_settings(width: u32, height: u32, settings: Settings) -> SpaceInvaders {
+        let mut space_invaders = SpaceInvaders {
             head: Point((width / 2) as i64, (height - 1) as i64),
-            body: (10..20).map(|i| Point(i as i64, 0)).collect(),
+            body: Vec::new(),
             bullets: Vec::new(),
             eggs: Vec::new(),
-            body_direction: BodyDirection::Right,
+            bunkers: Vec::new(),
+            body_direction: Direction::Right,
             width: width,
             height: height,
-            max_bullets: 200,
+            score: 0,
+            wave: 0,
+            settings: settings,
+            lives: settings.lives,
+            ufo: None,
+            power_ups: Vec::new(),
+            head_pos: center(Point((width / 2) as i64, (height - 1) as i64)),
+            head_prev: center(Point((width / 2) as i64, (height - 1) as i64)),
+            steering: None,
+            next_tick: 0.0,
+            rounds: settings.ammo.magazine,
+            shot_cooldown: 0.0,
+            reload_secs: 0.0,
+            effects: Vec::new(),
             count_bullets: 0,
+            formation_size: 0,
+            march_ticks: 0,
+            fire_ticks: settings.fire_ticks,
+            respawn_ticks: 0,
+            invulnerable_ticks: 0,
+            next_extra_life: EXTRA_LIFE_SCORE,
+            player_shot: false,
+            ufo_ticks: UFO_MAX_TICKS,
+            ufo_score: None,
+            ufo_score_ticks: 0,
+            wave_shots: 0,
+            wave_hits: 0,
+            last_bonus: 0,
+            bonus_ticks: 0,
+        };
+        space_invaders.build_bunkers();
+        space_invaders.next_wave();
+        space_invaders
+    }
+
+    // Evenly spread between the two sides, a few rows above the player.
+    fn build_bunkers(&mut self) {
+        let spacing = self.width as i64 / BUNKER_COUNT;
+        let left = (self.width as i64 - (BUNKER_COUNT - 1) * spacing - BUNKER_WIDTH) / 2;
+        let top = self.head.1 - BUNKER_RISE;
+        self.bunkers = (0..BUNKER_COUNT)
+            .flat_map(|i| BUNKER_SHAPE.iter().map(move |p| *p + Point(left + i * spacing, top)))
+            .collect();
+    }
+
+    // A fresh formation, starting lower and marching faster every wave.
+    fn next_wave(&mut self) {
+        if self.wave_shots > 0 {
+            self.last_bonus = ACCURACY_BONUS * self.wave_hits.min(self.wave_shots) / self.wave_shots;
+            self.bonus_ticks = BONUS_TICKS;
+            self.score += self.last_bonus;
+        }
+        self.wave_shots = 0;
+        self.wave_hits = 0;
+        self.ufo = None;
+        self.ufo_ticks = UFO_MAX_TICKS;
+
+        self.wave += 1;
+        if self.wave > 1 && !self.settings.bunkers_persist {
+            self.build_bunkers();
         }
+        let drop = (self.wave as i64 - 1).min(MAX_WAVE_DROP);
+        let left = (self.width as i64 - (FORMATION_COLUMNS - 1) * FORMATION_GAP) / 2;
+        self.body = FORMATION_ROWS.iter()
+            .enumerate()
+            .flat_map(|(row, kind)| {
+                (0..FORMATION_COLUMNS).map(move |col| {
+                    Alien {
+                        kind: *kind,
+                        pos: Point(left + col * FORMATION_GAP, FORMATION_TOP + drop + row as i64 * FORMATION_GAP),
+                    }
+                })
+            })
+            .collect();
+        self.formation_size = self.body.len();
+        self.body_direction = Direction::Right;
+        self.bullets.clear();
+        self.eggs.clear();
+        self.power_ups.clear();
+        self.march_ticks = 0;
+        self.fire_ticks = self.fire_interval();
     }
 
-    // pub fn set_max_bullets(&mut self, max: u32) {
-    //     self.max_bullets = max;
-    // }
+    /// Ticks between two steps of the formation, fewer as aliens die and
+    /// waves go by.
+    pub fn march_interval(&self) -> u32 {
+        let left = (self.settings.march_ticks as usize * self.body.len() + self.formation_size - 1) / self.formation_size.max(1);
+        (left as u32).saturating_sub((self.wave - 1) / 2).max(1)
+    }
+
+    /// Loads the gun differently, starting from a full magazine.
+    pub fn set_ammo(&mut self, ammo: Ammo) {
+        self.settings.ammo = ammo;
+        self.rounds = ammo.magazine;
+        self.reload_secs = 0.0;
+    }
+
+    /// Shots left in the magazine.
+    pub fn rounds(&self) -> u32 {
+        self.rounds
+    }
+
+    /// How far the magazine is loaded, while it is being loaded.
+    pub fn reload_progress(&self) -> Option<f64> {
+        if self.reload_secs > 0.0 {
+            Some(1.0 - self.reload_secs / self.settings.ammo.reload_secs)
+        } else {
+            None
+        }
+    }
+
+    /// Starts loading a fresh magazine, unless the one in is full.
+    pub fn reload(&mut self) {
+        if self.rounds < self.settings.ammo.magazine && self.reload_secs <= 0.0 {
+            self.rounds = 0;
+            self.reload_secs = self.settings.ammo.reload_secs;
+        }
+    }
 
-    pub fn gen_egg(&mut self) {
-        let index = rand::thread_rng().gen_range(0, self.body.len());
-        let p = self.body[index];
-        self.eggs.push(p + Point(0, 1));
+    /// The bonus for the accuracy of the last wave, shortly after it ended.
+    pub fn accuracy_bonus(&self) -> Option<u32> {
+        if self.bonus_ticks > 0 { Some(self.last_bonus) } else { None }
+    }
+
+    /// Ticks between two alien shots, fewer every wave and more as the
+    /// formation thins out.
+    pub fn fire_interval(&self) -> u32 {
+        let base = self.settings.fire_ticks.saturating_sub(self.wave - 1).max(MIN_FIRE_TICKS) as usize;
+        let size = self.formation_size.max(1);
+        (base * 2 * size / (size + self.body.len())) as u32
+    }
+
+    fn max_eggs(&self) -> usize {
+        (2 + self.wave as usize).min(MAX_EGGS)
+    }
+
+    fn update_fire(&mut self) {
+        if self.fire_ticks > 0 {
+            self.fire_ticks -= 1;
+        } else if !self.body.is_empty() && self.eggs.len() < self.max_eggs() {
+            self.gen_egg();
+            let interval = self.fire_interval();
+            self.fire_ticks = rand::thread_rng().gen_range(interval / 2, interval + 1);
+        }
     }
 
+    // Only the bottom-most alien of a column has a clear shot.
+    fn gen_egg(&mut self) {
+        let mut rng = rand::thread_rng();
+        let total = EGG_KINDS.iter().map(|k| k.weight()).sum::<u32>();
+        let mut roll = rng.gen_range(0, total);
+        let kind = *EGG_KINDS.iter().find(|k| {
+            let found = roll < k.weight();
+            roll = roll.saturating_sub(k.weight());
+            found
+        }).unwrap();
+
+        let column = if kind == EggKind::Rolling {
+            self.body.iter().map(|a| a.pos.0).min_by_key(|x| (x - self.head.0).abs()).unwrap()
+        } else {
+            self.body[rng.gen_range(0, self.body.len())].pos.0
+        };
+        let shooter = self.body.iter().filter(|a| a.pos.0 == column).map(|a| a.pos).max_by_key(|p| p.1).unwrap();
+        self.eggs.push(Egg::at(kind, shooter + Point(0, 1)));
+    }
+
+    /// One tick of the game, for the callers still stepping it on the grid.
     pub fn move_on(&mut self) -> Result<(), &'static str> {
-        self.del_collides();
+        self.update(TICK_SECS)
+    }
 
-        self.update_body();
-        self.update_bullets();
-        self.update_eggs();
+    /// Moves everything in flight `dt` seconds further, running a tick of the
+    /// formation, the firing and the timers whenever one is due.
+    pub fn update(&mut self, dt: f64) -> Result<(), &'static str> {
+        // a stalled window doesn't fast-forward the game
+        let dt = dt.min(TICK_SECS);
+        self.head_prev = self.head_pos;
+        for bullet in &mut self.bullets {
+            bullet.prev = bullet.pos;
+        }
+        for egg in &mut self.eggs {
+            egg.prev = egg.pos;
+        }
+        for power_up in &mut self.power_ups {
+            power_up.prev = power_up.pos;
+        }
+        if let Some(ref mut ufo) = self.ufo {
+            ufo.prev = ufo.pos;
+        }
 
-        if self.body.is_empty() {
-            Err("You win!!!")
-        } else if self.count_bullets >= self.max_bullets {
-            Err("You lose!!! No bullets left...")
-        } else if self.eggs.contains(&self.head) {
+        // summed up frame times fall just short of a whole tick
+        if self.next_tick < 1e-9 {
+            self.next_tick = (self.next_tick + TICK_SECS).max(0.0);
+            self.tick();
+        }
+        self.next_tick -= dt;
+        self.shot_cooldown = (self.shot_cooldown - dt).max(0.0);
+        if self.reload_secs > 0.0 {
+            self.reload_secs -= dt;
+            if self.reload_secs <= 0.0 {
+                self.reload_secs = 0.0;
+                self.rounds = self.settings.ammo.magazine;
+            }
+        }
+        self.fly(dt);
+
+        if self.player_shot {
+            self.player_shot = false;
+            self.lives -= 1;
+            self.respawn_ticks = RESPAWN_TICKS;
+            self.eggs.clear();
+            self.effects.clear();
+        }
+
+        if self.landed() {
+            Err("You lose!!! The aliens landed...")
+        } else if self.lives == 0 {
             Err("You lose!!! You were shoot...")
         }
         else {
@@ -83,88 +731,645 @@ impl SpaceInvaders {
         }
     }
 
+    fn tick(&mut self) {
+        self.update_lives();
+
+        if self.body.is_empty() {
+            self.next_wave();
+            return;
+        }
+
+        self.bonus_ticks = self.bonus_ticks.saturating_sub(1);
+        for effect in &mut self.effects {
+            effect.1 -= 1;
+        }
+        self.effects.retain(|e| e.1 > 0);
+        self.update_body();
+        self.update_ufo();
+        if !self.exploding() {
+            self.update_fire();
+        }
+    }
+
+    /// The player was hit and is exploding, it can't move or shoot.
+    pub fn exploding(&self) -> bool {
+        self.respawn_ticks > 0
+    }
+
+    /// The player just respawned, eggs hitting it break without costing a life.
+    pub fn invulnerable(&self) -> bool {
+        self.invulnerable_ticks > 0
+    }
+
+    fn update_lives(&mut self) {
+        if self.respawn_ticks > 0 {
+            self.respawn_ticks -= 1;
+            if self.respawn_ticks == 0 {
+                self.head = Point((self.width / 2) as i64, (self.height - 1) as i64);
+                self.head_pos = center(self.head);
+                self.head_prev = self.head_pos;
+                self.invulnerable_ticks = INVULNERABLE_TICKS;
+            }
+        } else if self.invulnerable_ticks > 0 {
+            self.invulnerable_ticks -= 1;
+        }
+
+        while self.score >= self.next_extra_life {
+            self.lives = (self.lives + 1).min(MAX_LIVES);
+            self.next_extra_life += EXTRA_LIFE_SCORE;
+        }
+    }
+
+    /// A cell to the left at once.
     pub fn head_left(&mut self) {
-        if self.head.0 > 0 {
-            self.head += Point(-1, 0);
+        if !self.exploding() {
+            let x = self.head_pos.0 - 1.0;
+            self.move_head(x);
         }
     }
 
+    /// A cell to the right at once.
     pub fn head_right(&mut self) {
-        if self.head.0 < (self.width as i64 - 1) {
-            self.head += Point(1, 0);
-        }
-    }
-
-    fn body_right(&mut self) {
-        // let mut tmp_body = HashSet::new();
-        // for i in &self.body {
-        //     tmp_body.push(Point(i.0 + 1, 0));
-        //     if i.0 == ((self.width - 2) as i64) {
-        //         self.body_direction = BodyDirection::Left;
-        //     }
-        // }
-        // self.body = tmp_body;
-        let tmp_body = self.body.clone();
-        self.body = tmp_body.iter().map(|p| {
-            if p.0 == (self.width as i64 - 2) {
-                self.body_direction = BodyDirection::Left;
-            }
-            *p + Point(1, 0)
-        }).collect();
+        if !self.exploding() {
+            let x = self.head_pos.0 + 1.0;
+            self.move_head(x);
+        }
     }
 
-    fn body_left(&mut self) {
-        let tmp_body = self.body.clone();
-        self.body = tmp_body.iter().map(|p| {
-            if p.0 == 1 {
-                self.body_direction = BodyDirection::Right;
-            }
-            *p + Point(-1, 0)
-        }).collect();
+    /// Keeps the player moving that way every update, until it stops. Only
+    /// `Left` and `Right` move it.
+    pub fn steer(&mut self, direction: Direction) {
+        self.steering = Some(direction);
+    }
+
+    /// Stops moving that way, if it was.
+    pub fn stop(&mut self, direction: Direction) {
+        if self.steering == Some(direction) {
+            self.steering = None;
+        }
+    }
+
+    // Kept on the field, with `head` following on the grid.
+    fn move_head(&mut self, x: f64) {
+        self.head_pos.0 = x.max(HEAD_HALF.0).min(self.width as f64 - HEAD_HALF.0);
+        self.head = self.head_pos.cell();
+    }
+
+    pub fn head_render_pos(&self, alpha: f64) -> Vec2 {
+        self.head_prev.lerp(self.head_pos, alpha)
+    }
+
+    /// The points of the last UFO hit and where it was, shortly after.
+    pub fn ufo_score(&self) -> Option<(Point, u32)> {
+        if self.ufo_score_ticks > 0 { self.ufo_score } else { None }
+    }
+
+    // Sent from a random side at random intervals, gone at the other one.
+    fn update_ufo(&mut self) {
+        self.ufo_score_ticks = self.ufo_score_ticks.saturating_sub(1);
+        if self.ufo.is_some() {
+            return;
+        }
+        if self.ufo_ticks > 0 {
+            self.ufo_ticks -= 1;
+        } else {
+            let mut rng = rand::thread_rng();
+            self.ufo_ticks = rng.gen_range(UFO_MIN_TICKS, UFO_MAX_TICKS + 1);
+            self.ufo = if rng.gen() {
+                Some(Ufo::at(Point(0, UFO_ROW), Vec2(UFO_SPEED, 0.0)))
+            } else {
+                Some(Ufo::at(Point(self.width as i64 - 1, UFO_ROW), Vec2(-UFO_SPEED, 0.0)))
+            };
+        }
+    }
+
+    // Whether the formation reached the row of the player.
+    fn landed(&self) -> bool {
+        self.body.iter().any(|a| a.pos.1 >= self.head.1)
+    }
+
+    fn body_shift(&mut self, delta: Point) {
+        for alien in &mut self.body {
+            alien.pos += delta;
+        }
     }
 
+    // The whole formation moves sideways, or one row down and back when it
+    // is already at the edge.
     fn update_body(&mut self) {
-        let r = rand::thread_rng().gen_range(1, 3);
-        for _ in 0..r {
-            match self.body_direction {
-                BodyDirection::Right => self.body_right(),
-                BodyDirection::Left => self.body_left(),
-            }
+        self.march_ticks += 1;
+        if self.march_ticks < self.march_interval() {
+            return;
         }
+        self.march_ticks = 0;
+
+        let field = Rect::sized(self.width, self.height);
+        let step = self.body_direction.delta();
+        if self.body.iter().any(|a| !field.contains(a.pos + step)) {
+            self.body_shift(Direction::Down.delta());
+            self.body_direction = self.body_direction.opposite();
+        } else {
+            self.body_shift(step);
+        }
+
+        // the formation walks through the bunkers, erasing them
+        let body = &self.body;
+        self.bunkers.retain(|b| !body.iter().any(|a| a.pos == *b));
+    }
+
+    /// The timed power-up effects in play and their ticks left.
+    pub fn effects(&self) -> &[(PowerUpKind, u32)] {
+        &self.effects
+    }
+
+    pub fn has_effect(&self, kind: PowerUpKind) -> bool {
+        self.effects.iter().any(|e| e.0 == kind)
     }
 
-    fn update_bullets(&mut self) {
-        self.bullets = self.bullets.iter().filter(|p| p.1 >= 0).map(|p| {
-            *p + Point(0, -2)
-        }).collect();
+    // Picking up an effect already in play starts it over.
+    fn collect(&mut self, kind: PowerUpKind) {
+        if kind == PowerUpKind::Ammo {
+            self.rounds = self.settings.ammo.magazine;
+            self.reload_secs = 0.0;
+        } else if let Some(effect) = self.effects.iter_mut().find(|e| e.0 == kind) {
+            effect.1 = kind.ticks();
+        } else {
+            self.effects.push((kind, kind.ticks()));
+        }
     }
 
-    fn update_eggs(&mut self) {
-        self.eggs = self.eggs.iter().filter(|p| p.1 < (self.height as i64)).map(|p| {
-            *p + Point(0, 1)
-        }).collect();
+    // Now and then where an alien was destroyed.
+    fn drop_power_up(&mut self, cell: Point) {
+        let mut rng = rand::thread_rng();
+        if rng.gen_range(0, 100) < POWER_UP_CHANCE {
+            let kind = POWER_UP_KINDS[rng.gen_range(0, POWER_UP_KINDS.len())];
+            self.power_ups.push(PowerUp::at(kind, cell));
+        }
     }
 
     pub fn shoot(&mut self) {
-        let bullet = self.head + Point(0, -1);
-        if !self.bullets.contains(&bullet) {
-            self.bullets.push(self.head + Point(0, -1));
-            self.count_bullets += 1;
-        }
-    }
-
-    fn del_collides(&mut self) {
-        let tmp_eggs = self.eggs.clone();
-
-        self.body = self.body.iter().filter(|p| {
-            !self.bullets.contains(*p)
-        }).cloned().collect();
-        self.eggs = self.eggs.iter().filter(|p| {
-            !(self.bullets.contains(*p) || self.bullets.contains(&Point(p.0, p.1 + 1)))
-        }).cloned().collect();
-        self.bullets = self.bullets.iter().filter(|p| {
-            !(tmp_eggs.contains(*p) || tmp_eggs.contains(&Point(p.0, p.1 - 1)))
-        }).cloned().collect();
-        // self.bullets = self.bullets.difference(&tmp_eggs).cloned().collect::<HashSet<_>>();
+        let bullet = Bullet {
+            piercing: self.has_effect(PowerUpKind::Piercing),
+            ..Bullet::new(self.head_pos + Vec2(0.0, -1.0))
+        };
+        if self.exploding() || self.rounds == 0 || self.shot_cooldown > 0.0 ||
+           self.bullets.len() >= self.settings.ammo.max_on_screen {
+            return;
+        }
+
+        self.bullets.push(bullet);
+        if self.has_effect(PowerUpKind::Spread) {
+            // the side pellets only as far as they fit on screen
+            let room = self.settings.ammo.max_on_screen - self.bullets.len();
+            for speed in [-SPREAD_SPEED, SPREAD_SPEED].iter().take(room) {
+                self.bullets.push(Bullet { vel: bullet.vel + Vec2(*speed, 0.0), ..bullet });
+            }
+        }
+        self.shot_cooldown = if self.has_effect(PowerUpKind::Rapid) {
+            self.settings.ammo.cooldown_secs / RAPID_FIRE
+        } else {
+            self.settings.ammo.cooldown_secs
+        };
+        // a spread shot still costs a single round
+        self.rounds -= 1;
+        if self.rounds == 0 {
+            self.reload();
+        }
+        self.count_bullets += 1;
+        self.wave_shots += 1;
+    }
+
+    // Sweeps the hitboxes of everything in flight along its path over `dt`
+    // and resolves the hits in the order they happen, so that nothing
+    // tunnels through anything however long the update.
+    fn fly(&mut self, dt: f64) {
+        let head_motion = match self.steering {
+            Some(_) if self.exploding() => Vec2(0.0, 0.0),
+            Some(direction) => Vec2(direction.delta().0 as f64 * HEAD_SPEED * dt, 0.0),
+            None => Vec2(0.0, 0.0),
+        };
+        let ufo_motion = self.ufo.map_or(Vec2(0.0, 0.0), |ufo| ufo.vel * dt);
+
+        // the formation may have marched into something, which hits at zero
+        let mut hits = Vec::new();
+        for (i, bullet) in self.bullets.iter().enumerate() {
+            let (hitbox, motion) = (bullet.hitbox(), bullet.vel * dt);
+            for (j, egg) in self.eggs.iter().enumerate() {
+                if let Some(t) = hitbox.sweep(motion - egg.kind.velocity() * dt, &egg.hitbox()) {
+                    hits.push((t, Hit::BulletEgg(i, j)));
+                }
+            }
+            for (a, alien) in self.body.iter().enumerate() {
+                if let Some(t) = hitbox.sweep(motion, &alien.hitbox()) {
+                    hits.push((t, Hit::BulletAlien(i, a)));
+                }
+            }
+            if let Some(ufo) = self.ufo {
+                if let Some(t) = hitbox.sweep(motion - ufo_motion, &ufo.hitbox()) {
+                    hits.push((t, Hit::BulletUfo(i)));
+                }
+            }
+            for (c, cell) in self.bunkers.iter().enumerate() {
+                if let Some(t) = hitbox.sweep(motion, &Aabb::new(center(*cell), BUNKER_HALF)) {
+                    hits.push((t, Hit::BulletBunker(i, c)));
+                }
+            }
+        }
+        let head = Aabb::new(self.head_pos, HEAD_HALF);
+        for (j, egg) in self.eggs.iter().enumerate() {
+            let (hitbox, motion) = (egg.hitbox(), egg.kind.velocity() * dt);
+            for (c, cell) in self.bunkers.iter().enumerate() {
+                if let Some(t) = hitbox.sweep(motion, &Aabb::new(center(*cell), BUNKER_HALF)) {
+                    hits.push((t, Hit::EggBunker(j, c)));
+                }
+            }
+            if !self.exploding() {
+                if let Some(t) = hitbox.sweep(motion - head_motion, &head) {
+                    hits.push((t, Hit::EggHead(j)));
+                }
+            }
+        }
+        if !self.exploding() {
+            let motion = Vec2(0.0, POWER_UP_SPEED * dt) - head_motion;
+            for (k, power_up) in self.power_ups.iter().enumerate() {
+                if let Some(t) = power_up.hitbox().sweep(motion, &head) {
+                    hits.push((t, Hit::PowerUpHead(k)));
+                }
+            }
+        }
+        hits.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut dead_bullets = vec![false; self.bullets.len()];
+        let mut dead_eggs = vec![false; self.eggs.len()];
+        let mut dead_aliens = vec![false; self.body.len()];
+        let mut dead_bunkers = vec![false; self.bunkers.len()];
+        let mut collected = vec![false; self.power_ups.len()];
+        let mut drops = Vec::new();
+        for &(t, hit) in &hits {
+            match hit {
+                Hit::BulletEgg(i, j) if !dead_bullets[i] && !dead_eggs[j] => {
+                    dead_bullets[i] = !self.bullets[i].piercing;
+                    dead_eggs[j] = true;
+                }
+                Hit::BulletAlien(i, a) if !dead_bullets[i] && !dead_aliens[a] => {
+                    dead_bullets[i] = !self.bullets[i].piercing;
+                    dead_aliens[a] = true;
+                    self.score += self.body[a].kind.points();
+                    self.wave_hits += 1;
+                    drops.push(self.body[a].pos);
+                }
+                Hit::BulletUfo(i) if !dead_bullets[i] && self.ufo.is_some() => {
+                    let points = UFO_POINTS[self.count_bullets as usize % UFO_POINTS.len()];
+                    let ufo = self.ufo.take().unwrap();
+                    dead_bullets[i] = true;
+                    self.wave_hits += 1;
+                    self.score += points;
+                    self.ufo_score = Some(((ufo.pos + ufo_motion * t).cell(), points));
+                    self.ufo_score_ticks = UFO_SCORE_TICKS;
+                }
+                Hit::BulletBunker(i, c) if !dead_bullets[i] && !dead_bunkers[c] => {
+                    dead_bullets[i] = true;
+                    dead_bunkers[c] = true;
+                }
+                Hit::EggBunker(j, c) if !dead_eggs[j] && !dead_bunkers[c] => {
+                    dead_eggs[j] = true;
+                    dead_bunkers[c] = true;
+                }
+                Hit::EggHead(j) if !dead_eggs[j] => {
+                    dead_eggs[j] = true;
+                    // an invulnerable or shielded player just soaks it up
+                    self.player_shot |= !self.invulnerable() && !self.has_effect(PowerUpKind::Shield);
+                }
+                Hit::PowerUpHead(k) if !collected[k] => {
+                    collected[k] = true;
+                    let kind = self.power_ups[k].kind;
+                    self.collect(kind);
+                }
+                _ => {}
+            }
+        }
+
+        let mut i = 0;
+        self.bullets.retain(|_| {
+            i += 1;
+            !dead_bullets[i - 1]
+        });
+        let mut j = 0;
+        self.eggs.retain(|_| {
+            j += 1;
+            !dead_eggs[j - 1]
+        });
+        let mut a = 0;
+        self.body.retain(|_| {
+            a += 1;
+            !dead_aliens[a - 1]
+        });
+        let mut c = 0;
+        self.bunkers.retain(|_| {
+            c += 1;
+            !dead_bunkers[c - 1]
+        });
+        let mut k = 0;
+        self.power_ups.retain(|_| {
+            k += 1;
+            !collected[k - 1]
+        });
+        for cell in drops {
+            self.drop_power_up(cell);
+        }
+
+        for bullet in &mut self.bullets {
+            bullet.pos = bullet.pos + bullet.vel * dt;
+        }
+        for egg in &mut self.eggs {
+            egg.pos = egg.pos + egg.kind.velocity() * dt;
+        }
+        for power_up in &mut self.power_ups {
+            power_up.pos.1 += POWER_UP_SPEED * dt;
+        }
+        let x = self.head_pos.0 + head_motion.0;
+        self.move_head(x);
+        let width = self.width as f64;
+        self.ufo = self.ufo
+            .map(|ufo| Ufo { pos: ufo.pos + ufo_motion, ..ufo })
+            .filter(|ufo| ufo.pos.0 + UFO_HALF.0 > 0.0 && ufo.pos.0 - UFO_HALF.0 < width);
+
+        // gone once the whole hitbox is off the field
+        let height = self.height as f64;
+        self.bullets.retain(|b| {
+            b.pos.1 + BULLET_HALF.1 > 0.0 && b.pos.0 + BULLET_HALF.0 > 0.0 && b.pos.0 - BULLET_HALF.0 < width
+        });
+        self.eggs.retain(|e| e.pos.1 - EGG_HALF.1 < height);
+        self.power_ups.retain(|p| p.pos.1 - POWER_UP_HALF.1 < height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Octopuses at `aliens`, no bunkers, nothing fired and no UFO coming.
+    fn field(aliens: &[Point]) -> SpaceInvaders {
+        let mut space_invaders = SpaceInvaders::new_with_bounds(30, 30);
+        space_invaders.body = aliens.iter().map(|p| Alien { kind: AlienKind::Octopus, pos: *p }).collect();
+        space_invaders.formation_size = space_invaders.body.len();
+        space_invaders.bunkers.clear();
+        space_invaders.fire_ticks = u32::max_value();
+        space_invaders.ufo_ticks = u32::max_value();
+        space_invaders
+    }
+
+    fn bullets(cells: &[Point]) -> Vec<Bullet> {
+        cells.iter().map(|p| Bullet::at(*p)).collect()
+    }
+
+    #[test]
+    fn bullet_and_egg_crossing_head_on() {
+        let mut space_invaders = field(&[Point(25, 7)]);
+        // they swap cells a third of the way into the tick
+        space_invaders.bullets = bullets(&[Point(5, 10)]);
+        space_invaders.eggs = vec![Egg::at(EggKind::Rolling, Point(5, 9))];
+        assert_eq!(space_invaders.move_on(), Ok(()));
+        assert!(space_invaders.bullets.is_empty());
+        assert!(space_invaders.eggs.is_empty());
+
+        // a cell apart, they meet later in the same tick
+        space_invaders.bullets = bullets(&[Point(5, 12)]);
+        space_invaders.eggs = vec![Egg::at(EggKind::Rolling, Point(5, 9))];
+        assert_eq!(space_invaders.move_on(), Ok(()));
+        assert!(space_invaders.bullets.is_empty());
+        assert!(space_invaders.eggs.is_empty());
+    }
+
+    #[test]
+    fn bullet_meets_slow_egg() {
+        let mut space_invaders = field(&[Point(25, 7)]);
+        space_invaders.bullets = bullets(&[Point(5, 12)]);
+        space_invaders.eggs = vec![Egg::at(EggKind::Plunger, Point(5, 10))];
+        assert_eq!(space_invaders.move_on(), Ok(()));
+        assert!(space_invaders.bullets.is_empty());
+        assert!(space_invaders.eggs.is_empty());
+    }
+
+    #[test]
+    fn bullet_does_not_tunnel_through_alien() {
+        let mut space_invaders = field(&[Point(5, 9), Point(25, 7)]);
+        space_invaders.bullets = bullets(&[Point(5, 11)]);
+        assert_eq!(space_invaders.move_on(), Ok(()));
+        assert_eq!(space_invaders.body.len(), 1);
+        assert!(space_invaders.bullets.is_empty());
+        assert_eq!(space_invaders.score, AlienKind::Octopus.points());
+    }
+
+    #[test]
+    fn alien_marches_into_bullet() {
+        let mut space_invaders = field(&[Point(10, 10), Point(2, 7)]);
+        space_invaders.march_ticks = space_invaders.march_interval() - 1;
+        space_invaders.bullets = bullets(&[Point(11, 10)]);
+        assert_eq!(space_invaders.move_on(), Ok(()));
+        assert_eq!(space_invaders.body, vec![Alien { kind: AlienKind::Octopus, pos: Point(3, 7) }]);
+        assert!(space_invaders.bullets.is_empty());
+    }
+
+    #[test]
+    fn alien_steps_down_into_bullet_at_edge() {
+        let mut space_invaders = field(&[Point(29, 10), Point(20, 7)]);
+        space_invaders.march_ticks = space_invaders.march_interval() - 1;
+        space_invaders.bullets = bullets(&[Point(29, 11)]);
+        assert_eq!(space_invaders.move_on(), Ok(()));
+        assert_eq!(space_invaders.body, vec![Alien { kind: AlienKind::Octopus, pos: Point(20, 8) }]);
+        assert!(space_invaders.bullets.is_empty());
+        assert_eq!(space_invaders.body_direction, Direction::Left);
+    }
+
+    #[test]
+    fn hits_at_top_edge() {
+        // the alien on the top row is hit before the bullet leaves the screen
+        let mut space_invaders = field(&[Point(3, 0), Point(25, 7)]);
+        space_invaders.bullets = bullets(&[Point(3, 1), Point(8, 0)]);
+        assert_eq!(space_invaders.move_on(), Ok(()));
+        assert_eq!(space_invaders.body.len(), 1);
+        assert!(space_invaders.bullets.is_empty());
+    }
+
+    #[test]
+    fn ufo_hit_at_side_edge() {
+        let mut space_invaders = field(&[Point(25, 7)]);
+        // half way off the field already
+        space_invaders.ufo = Some(Ufo::at(Point(0, UFO_ROW), Vec2(-UFO_SPEED, 0.0)));
+        space_invaders.bullets = bullets(&[Point(0, UFO_ROW + 1)]);
+        assert_eq!(space_invaders.move_on(), Ok(()));
+        assert_eq!(space_invaders.ufo, None);
+        assert!(space_invaders.bullets.is_empty());
+        assert_eq!(space_invaders.ufo_score().map(|(p, _)| p), Some(Point(0, UFO_ROW)));
+    }
+
+    #[test]
+    fn eggs_at_bottom_edge() {
+        let mut space_invaders = field(&[Point(25, 7)]);
+        let head = space_invaders.head;
+        let lives = space_invaders.lives;
+        // one falls off the screen, the fast one can't jump over the player
+        space_invaders.eggs = vec![Egg::at(EggKind::Plunger, Point(3, 29)),
+                                   Egg::at(EggKind::Rolling, head + Point(0, -2))];
+        assert_eq!(space_invaders.move_on(), Ok(()));
+        assert!(space_invaders.eggs.is_empty());
+        assert_eq!(space_invaders.lives, lives - 1);
+        assert!(space_invaders.exploding());
+    }
+
+    #[test]
+    fn sweep_finds_first_contact() {
+        let bullet = Aabb::new(Vec2(0.5, 5.5), Vec2(0.5, 0.5));
+        let cell = Aabb::new(Vec2(0.5, 1.5), Vec2(0.5, 0.5));
+        assert_eq!(bullet.sweep(Vec2(0.0, -6.0), &cell), Some(0.5));
+        assert_eq!(bullet.sweep(Vec2(0.0, -2.0), &cell), None);
+        // passing alongside, and moving away
+        assert_eq!(bullet.sweep(Vec2(0.0, -6.0), &Aabb::new(Vec2(1.5, 3.5), Vec2(0.5, 0.5))), None);
+        assert_eq!(bullet.sweep(Vec2(0.0, 6.0), &cell), None);
+        assert!(bullet.overlaps(&Aabb::new(Vec2(0.9, 5.9), Vec2(0.5, 0.5))));
+        assert_eq!(bullet.sweep(Vec2(0.0, 6.0), &Aabb::new(Vec2(0.9, 5.9), Vec2(0.5, 0.5))), Some(0.0));
+    }
+
+    #[test]
+    fn same_flight_at_any_update_rate() {
+        let mut ticked = field(&[Point(5, 15), Point(25, 7)]);
+        ticked.bullets = bullets(&[Point(5, 20), Point(8, 20)]);
+        let mut smooth = field(&[Point(5, 15), Point(25, 7)]);
+        smooth.bullets = ticked.bullets.clone();
+
+        for _ in 0..3 {
+            assert_eq!(ticked.move_on(), Ok(()));
+        }
+        for _ in 0..36 {
+            assert_eq!(smooth.update(TICK_SECS / 12.0), Ok(()));
+        }
+        assert_eq!(ticked.body, smooth.body);
+        assert_eq!(ticked.score, smooth.score);
+        assert_eq!(ticked.bullets.len(), 1);
+        assert_eq!(smooth.bullets.len(), 1);
+        assert!((ticked.bullets[0].pos.1 - smooth.bullets[0].pos.1).abs() < 1e-6);
+        assert_eq!(smooth.bullets[0].cell(), Point(8, 14));
+    }
+
+    #[test]
+    fn steering_moves_smoothly_and_stops_at_the_edge() {
+        let mut space_invaders = field(&[Point(25, 7)]);
+        let start = space_invaders.head_pos;
+        space_invaders.steer(Direction::Left);
+        assert_eq!(space_invaders.update(0.05), Ok(()));
+        assert!((space_invaders.head_pos.0 - (start.0 - HEAD_SPEED * 0.05)).abs() < 1e-9);
+        let halfway = space_invaders.head_render_pos(0.5);
+        assert!((halfway.0 - (start.0 - HEAD_SPEED * 0.025)).abs() < 1e-9);
+
+        for _ in 0..100 {
+            assert_eq!(space_invaders.update(0.05), Ok(()));
+        }
+        assert_eq!(space_invaders.head, Point(0, 29));
+        space_invaders.stop(Direction::Right);
+        space_invaders.head_right();
+        assert_eq!(space_invaders.head, Point(1, 29));
+        space_invaders.stop(Direction::Left);
+        assert_eq!(space_invaders.update(0.05), Ok(()));
+        assert_eq!(space_invaders.head, Point(1, 29));
+    }
+
+    #[test]
+    fn spread_power_up_collected_by_touch() {
+        let mut space_invaders = field(&[Point(25, 7)]);
+        let head = space_invaders.head;
+        space_invaders.power_ups = vec![PowerUp::at(PowerUpKind::Spread, head + Point(0, -1)),
+                                        PowerUp::at(PowerUpKind::Shield, head + Point(3, -1))];
+        assert_eq!(space_invaders.move_on(), Ok(()));
+        assert_eq!(space_invaders.effects(), &[(PowerUpKind::Spread, PowerUpKind::Spread.ticks())]);
+        assert_eq!(space_invaders.power_ups.len(), 1);
+
+        space_invaders.shoot();
+        assert_eq!(space_invaders.bullets.len(), 3);
+        assert_eq!(space_invaders.rounds(), space_invaders.settings.ammo.magazine - 1);
+        // still cooling down
+        space_invaders.shoot();
+        assert_eq!(space_invaders.bullets.len(), 3);
+    }
+
+    #[test]
+    fn piercing_bullet_goes_through_column() {
+        let mut space_invaders = field(&[Point(5, 26), Point(5, 24), Point(25, 7)]);
+        space_invaders.effects = vec![(PowerUpKind::Piercing, 10)];
+        space_invaders.head_pos = center(Point(5, 29));
+        space_invaders.head = Point(5, 29);
+        space_invaders.shoot();
+        for _ in 0..2 {
+            assert_eq!(space_invaders.move_on(), Ok(()));
+        }
+        assert_eq!(space_invaders.body.len(), 1);
+        assert_eq!(space_invaders.bullets.len(), 1);
+    }
+
+    #[test]
+    fn shield_stops_eggs_until_it_wears_off() {
+        let mut space_invaders = field(&[Point(25, 7)]);
+        let head = space_invaders.head;
+        let lives = space_invaders.lives;
+        space_invaders.effects = vec![(PowerUpKind::Shield, 2)];
+        space_invaders.eggs = vec![Egg::at(EggKind::Plunger, head + Point(0, -1))];
+        assert_eq!(space_invaders.move_on(), Ok(()));
+        assert!(space_invaders.eggs.is_empty());
+        assert_eq!(space_invaders.lives, lives);
+
+        space_invaders.eggs = vec![Egg::at(EggKind::Plunger, head + Point(0, -1))];
+        assert_eq!(space_invaders.move_on(), Ok(()));
+        assert!(space_invaders.effects().is_empty());
+        assert_eq!(space_invaders.lives, lives - 1);
+    }
+
+    #[test]
+    fn empty_magazine_reloads() {
+        let mut space_invaders = field(&[Point(25, 7)]);
+        space_invaders.set_ammo(Ammo::new(2, 0.5, 0.1, 5));
+        space_invaders.shoot();
+        assert_eq!(space_invaders.update(0.1), Ok(()));
+        space_invaders.shoot();
+        assert_eq!(space_invaders.bullets.len(), 2);
+        assert_eq!(space_invaders.rounds(), 0);
+        assert_eq!(space_invaders.reload_progress(), Some(0.0));
+
+        assert_eq!(space_invaders.update(0.2), Ok(()));
+        space_invaders.shoot();
+        assert_eq!(space_invaders.bullets.len(), 2);
+        assert_eq!(space_invaders.update(0.2), Ok(()));
+        assert_eq!(space_invaders.update(0.1), Ok(()));
+        assert_eq!(space_invaders.reload_progress(), None);
+        assert_eq!(space_invaders.rounds(), 2);
+        space_invaders.shoot();
+        assert_eq!(space_invaders.rounds(), 1);
+    }
+
+    #[test]
+    fn bullets_on_screen_are_limited() {
+        let mut space_invaders = field(&[Point(25, 7)]);
+        space_invaders.set_ammo(Ammo::new(10, 1.0, 0.05, 2));
+        for _ in 0..3 {
+            space_invaders.shoot();
+            assert_eq!(space_invaders.update(0.05), Ok(()));
+        }
+        assert_eq!(space_invaders.bullets.len(), 2);
+        assert_eq!(space_invaders.rounds(), 8);
+
+        // a spread shot is cut down to what still fits
+        space_invaders.bullets.clear();
+        space_invaders.set_ammo(Ammo::new(10, 1.0, 0.05, 4));
+        space_invaders.effects = vec![(PowerUpKind::Spread, 10)];
+        for &expected in &[3, 4, 4] {
+            space_invaders.shoot();
+            assert_eq!(space_invaders.bullets.len(), expected);
+            assert_eq!(space_invaders.update(0.05), Ok(()));
+        }
+
+        // a manual reload throws away what is left
+        space_invaders.reload();
+        assert_eq!(space_invaders.rounds(), 0);
+        space_invaders.reload();
+        assert_eq!(space_invaders.reload_progress(), Some(0.0));
     }
 }
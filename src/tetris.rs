@@ -1,40 +1,69 @@
 extern crate rand;
 
 use rand::Rng;
-use std::ops::{Add, AddAssign};
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub struct Point(pub i64, pub i64);
+use geometry::Grid;
 
-impl Add<Point> for Point {
-    type Output = Point;
-    fn add(self, rhs: Point) -> Point {
-        Point(self.0 + rhs.0, self.1 + rhs.1)
-    }
+pub use geometry::{Direction, Point};
+
+// moves and turns that can put off locking a landed piece
+const MAX_LOCK_RESETS: u32 = 15;
+
+/// How fast the pieces fall, how long a landed one can still slide and how
+/// long cleared lines flash before the stack collapses.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Speed {
+    pub gravity_secs: f64,
+    pub lock_delay_secs: f64,
+    pub clear_secs: f64,
 }
 
-impl AddAssign<Point> for Point {
-    fn add_assign(&mut self, rhs: Point) {
-        self.0 += rhs.0;
-        self.1 += rhs.1;
+impl Default for Speed {
+    fn default() -> Speed {
+        Speed {
+            gravity_secs: 0.2,
+            lock_delay_secs: 0.2,
+            clear_secs: 0.6,
+        }
     }
 }
 
-pub enum Direction {
-    Left,
-    Right,
-    Down,
+#[derive(Debug, Clone)]
+pub struct LineClear {
+    pub lines: Vec<i64>,
+    pub points: u32,
+    pub frames_left: u32,
+}
+
+impl LineClear {
+    pub fn name(&self) -> &'static str {
+        match self.lines.len() {
+            1 => "Single",
+            2 => "Double",
+            3 => "Triple",
+            _ => "Tetris",
+        }
+    }
 }
 
 pub struct Tetris {
     now_shape: Vec<Point>,
-    bottom: Vec<Point>,
+    // the settled blocks, one cell per column of the well
+    bottom: Grid<bool>,
+    // a block settled above the well
+    topped_out: bool,
     width: u32,
     height: u32,
     offset: Point,
     score: u32,
     now_index: usize,
     next_index: usize,
+    pending_clear: Option<LineClear>,
+    speed: Speed,
+    // seconds the piece has been resting on the stack, and how often that
+    // was started over by moving it
+    landed_secs: f64,
+    lock_resets: u32,
 }
 
 const SHAPES: [[Point; 4]; 7] = [[Point(-1, 0), Point(0, 0), Point(0, 1), Point(1, 0)], // -|-
@@ -51,29 +80,65 @@ fn rand_index() -> usize {
 
 impl Tetris {
     pub fn new_with_bounds(width: u32, height: u32) -> Tetris {
+        Tetris::new_with_speed(width, height, Speed::default())
+    }
+
+    pub fn new_with_speed(width: u32, height: u32, speed: Speed) -> Tetris {
         Tetris {
             now_shape: SHAPES[0].iter().cloned().collect::<Vec<Point>>(),
-            bottom: Vec::new(),
+            bottom: Grid::new(width / 2, height, false),
+            topped_out: false,
             width: width,
             height: height,
             offset: Point(width as i64 / 4, 0),
             score: 0,
             now_index: 0,
             next_index: rand_index(),
+            pending_clear: None,
+            speed: speed,
+            landed_secs: 0.0,
+            lock_resets: 0,
         }
     }
 
     pub fn reset(&mut self) {
-        self.bottom.clear();
+        self.bottom = Grid::new(self.width / 2, self.height, false);
+        self.topped_out = false;
         self.score = 0;
         self.offset = Point(self.width as i64 / 4, 0);
+        self.pending_clear = None;
+        self.landed_secs = 0.0;
+        self.lock_resets = 0;
     }
 
-    pub fn bottom(&self) -> &Vec<Point> {
-        &self.bottom
+    /// Number of updates the cleared lines stay on screen before the stack
+    /// collapses, as close to `clear_secs` as the gravity allows.
+    pub fn clear_frames(&self) -> u32 {
+        ((self.speed.clear_secs / self.speed.gravity_secs).round() as u32).max(1)
+    }
+
+    pub fn pending_clear(&self) -> Option<&LineClear> {
+        self.pending_clear.as_ref()
+    }
+
+    pub fn is_clearing(&self) -> bool {
+        self.pending_clear.is_some()
+    }
+
+    /// The settled blocks.
+    pub fn bottom(&self) -> Vec<Point> {
+        self.bottom
+            .bounds()
+            .points()
+            .into_iter()
+            .filter(|p| self.bottom.get(*p) == Some(&true))
+            .collect()
     }
 
     pub fn now_shape(&self) -> Vec<Point> {
+        if self.is_clearing() {
+            return Vec::new();
+        }
         self.now_shape
             .iter()
             .map(|p| *p + self.offset)
@@ -96,19 +161,33 @@ impl Tetris {
         self.score
     }
 
+    /// Seconds between two `move_on` calls.
+    pub fn move_interval(&self) -> f64 {
+        self.speed.gravity_secs
+    }
+
     pub fn move_on(&mut self) -> Result<(), &'static str> {
         // if self.score >= 2000 {
         //     return Err("You win! Score >= 2000...");
         // }
+        if self.is_clearing() {
+            self.step_clear();
+            return self.next_or_over();
+        }
+
         if self.move_collides(Direction::Down) {
+            // still free to slide until the lock delay is up
+            self.landed_secs += self.speed.gravity_secs;
+            if self.landed_secs < self.speed.lock_delay_secs {
+                return Ok(());
+            }
+            self.landed_secs = 0.0;
+            self.lock_resets = 0;
             self.push_bottom();
-            self.del_full_lines();
-            self.update_shape();
-            if self.game_over() {
-                Err("You lose! Game over...")
-            } else {
-                Ok(())
+            if self.start_clear() {
+                return Ok(());
             }
+            self.next_or_over()
         } else {
             self.down_once();
             Ok(())
@@ -137,57 +216,59 @@ impl Tetris {
     fn rotated_shape(&self) -> Vec<Point> {
         self.now_shape
             .iter()
-            .map(|p| Point(p.1, -p.0))
+            .map(|p| p.rotate_ccw())
             .collect()
     }
 
     pub fn rotate(&mut self) {
-        if self.now_index != 6 {
+        if self.now_index != 6 && !self.is_clearing() {
             let tmp_shape = self.rotated_shape();
-            let rotate_collides: bool = tmp_shape.iter().any(|p| {
-                let next_p = self.offset + *p;
-                next_p.0 < 0 || next_p.0 >= (self.width as i64 / 2) ||
-                next_p.1 >= (self.height as i64) || self.bottom.contains(&next_p)
-            });
+            let rotate_collides: bool = tmp_shape.iter().any(|p| self.collides(self.offset + *p));
             if !rotate_collides {
                 self.now_shape = tmp_shape;
+                self.delay_lock();
             }
         }
     }
 
     fn move_collides(&self, direction: Direction) -> bool {
-        let mut tmp_offset = self.offset;
-        match direction {
-            Direction::Down => tmp_offset += Point(0, 1),
-            Direction::Left => tmp_offset += Point(-1, 0),
-            Direction::Right => tmp_offset += Point(1, 0),
-        }
-        self.now_shape.iter().any(|p| {
-            let next_p = tmp_offset + *p;
-            next_p.0 < 0 || next_p.0 >= (self.width as i64 / 2) ||
-            next_p.1 >= (self.height as i64) || self.bottom.contains(&next_p)
-        })
+        let tmp_offset = self.offset + direction.delta();
+        self.now_shape.iter().any(|p| self.collides(tmp_offset + *p))
+    }
+
+    // Off the sides or the floor of the well, or on a settled block. Above the
+    // well is free, pieces turn in there.
+    fn collides(&self, p: Point) -> bool {
+        let well = self.bottom.bounds();
+        p.0 < well.left || p.0 >= well.right() || p.1 >= well.bottom() ||
+        self.bottom.get(p) == Some(&true)
     }
 
     pub fn down_once(&mut self) {
-        if !self.move_collides(Direction::Down) {
-            self.offset += Point(0, 1);
+        if !self.is_clearing() && !self.move_collides(Direction::Down) {
+            self.offset += Direction::Down.delta();
+            self.landed_secs = 0.0;
         }
     }
 
     pub fn right_once(&mut self) {
-        if !self.move_collides(Direction::Right) {
-            self.offset += Point(1, 0);
+        if !self.is_clearing() && !self.move_collides(Direction::Right) {
+            self.offset += Direction::Right.delta();
+            self.delay_lock();
         }
     }
 
     pub fn left_once(&mut self) {
-        if !self.move_collides(Direction::Left) {
-            self.offset += Point(-1, 0);
+        if !self.is_clearing() && !self.move_collides(Direction::Left) {
+            self.offset += Direction::Left.delta();
+            self.delay_lock();
         }
     }
 
     pub fn down_immediately(&mut self) {
+        if self.is_clearing() {
+            return;
+        }
         // loop {
         //     if self.move_collides(Direction::Down) {
         //         break;
@@ -196,13 +277,26 @@ impl Tetris {
         // }
 
         while !self.move_collides(Direction::Down) {
-            self.offset += Point(0, 1);
+            self.offset += Direction::Down.delta();
+        }
+        // a dropped piece locks on the next move
+        self.landed_secs = self.speed.lock_delay_secs;
+        self.lock_resets = MAX_LOCK_RESETS;
+    }
+
+    // A landed piece that slid or turned gets the whole lock delay again.
+    fn delay_lock(&mut self) {
+        if self.landed_secs > 0.0 && self.lock_resets < MAX_LOCK_RESETS {
+            self.landed_secs = 0.0;
+            self.lock_resets += 1;
         }
     }
 
     fn push_bottom(&mut self) {
         for p in &self.now_shape {
-            self.bottom.push(*p + self.offset);
+            if !self.bottom.set(*p + self.offset, true) {
+                self.topped_out = true;
+            }
         }
     }
 
@@ -213,29 +307,76 @@ impl Tetris {
         }
         self.offset = Point(self.width as i64 / 4, 0);
         self.now_index = self.next_index;
+        self.landed_secs = 0.0;
+        self.lock_resets = 0;
 
         // let i = rand::thread_rng().gen_range(0, 7usize);
         self.next_index = rand_index();
     }
 
-    fn del_full_lines(&mut self) {
+    fn next_or_over(&mut self) -> Result<(), &'static str> {
+        if self.is_clearing() {
+            return Ok(());
+        }
+        self.update_shape();
+        if self.game_over() {
+            Err("You lose! Game over...")
+        } else {
+            Ok(())
+        }
+    }
+
+    // Marks the full lines as pending, the stack collapses once the animation is done.
+    fn start_clear(&mut self) -> bool {
         let full_lines = self.full_lines();
+        if full_lines.is_empty() {
+            return false;
+        }
+
+        let before = self.score;
         self.add_score(full_lines.len());
+        self.pending_clear = Some(LineClear {
+            lines: full_lines,
+            points: self.score - before,
+            frames_left: self.clear_frames(),
+        });
+        true
+    }
+
+    fn step_clear(&mut self) {
+        let done = match self.pending_clear {
+            Some(ref mut clear) => {
+                if clear.frames_left > 0 {
+                    clear.frames_left -= 1;
+                }
+                clear.frames_left == 0
+            }
+            None => false,
+        };
+        if done {
+            let lines = self.pending_clear.take().unwrap().lines;
+            self.del_full_lines(lines);
+        }
+    }
 
+    fn del_full_lines(&mut self, full_lines: Vec<i64>) {
+        let well = self.bottom.bounds();
         for line in full_lines {
-            self.bottom = self.bottom
-                .iter()
-                .filter(|p| p.1 != line)
-                .map(|p| if p.1 < line { *p + Point(0, 1) } else { *p })
-                .collect();
+            for y in (well.top..line + 1).rev() {
+                for x in well.left..well.right() {
+                    let above = self.bottom.get(Point(x, y - 1)).cloned().unwrap_or(false);
+                    self.bottom.set(Point(x, y), above);
+                }
+            }
         }
     }
 
     fn full_lines(&self) -> Vec<i64> {
         let mut full_lines = Vec::new();
 
-        for y in 0..(self.height as i64) {
-            let is_full = (0..(self.width as i64 / 2)).all(|x| self.bottom.contains(&Point(x, y)));
+        let well = self.bottom.bounds();
+        for y in well.top..well.bottom() {
+            let is_full = (well.left..well.right()).all(|x| self.bottom.get(Point(x, y)) == Some(&true));
             if is_full {
                 full_lines.push(y);
             }
@@ -266,14 +407,156 @@ impl Tetris {
     }
 
     fn game_over(&self) -> bool {
-        for p in &self.bottom {
-            if p.1 <= 0 {
-                return true;
-            }
-        }
-        false
+        let well = self.bottom.bounds();
+        self.topped_out ||
+        (well.left..well.right()).any(|x| self.bottom.get(Point(x, well.top)) == Some(&true))
     }
 }
 // fn main() {
 //     let t = Tetris::new_with_bounds(600, 400);
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A well ten columns wide and twenty rows deep.
+    fn well(speed: Speed) -> Tetris {
+        Tetris::new_with_speed(20, 20, speed)
+    }
+
+    fn fill_row(tetris: &mut Tetris, y: i64) {
+        for x in 0..10 {
+            tetris.bottom.set(Point(x, y), true);
+        }
+    }
+
+    #[test]
+    fn lines_clear_after_the_flash() {
+        let mut tetris = well(Speed::default());
+        fill_row(&mut tetris, 19);
+        let next = tetris.next_index;
+        assert!(tetris.start_clear());
+        assert_eq!(tetris.pending_clear().unwrap().lines, vec![19]);
+        assert_eq!(tetris.pending_clear().unwrap().frames_left, 3);
+        for _ in 0..2 {
+            assert_eq!(tetris.move_on(), Ok(()));
+            assert!(tetris.is_clearing());
+            assert_eq!(tetris.bottom().len(), 10);
+        }
+        assert_eq!(tetris.move_on(), Ok(()));
+        assert!(!tetris.is_clearing());
+        assert!(tetris.bottom().is_empty());
+        assert_eq!(tetris.now_index, next);
+    }
+
+    #[test]
+    fn clear_records_the_points_it_scored() {
+        let mut tetris = well(Speed::default());
+        fill_row(&mut tetris, 18);
+        fill_row(&mut tetris, 19);
+        for &p in &[Point(4, 18), Point(5, 18), Point(4, 19), Point(5, 19)] {
+            tetris.bottom.set(p, false);
+        }
+        tetris.score = 100;
+        tetris.now_index = 6;
+        tetris.now_shape = SHAPES[6].to_vec();
+        tetris.offset = Point(4, 18);
+        assert_eq!(tetris.move_on(), Ok(()));
+        let clear = tetris.pending_clear().unwrap();
+        assert_eq!(clear.lines, vec![18, 19]);
+        assert_eq!(clear.points, 300);
+        assert_eq!(clear.name(), "Double");
+        assert_eq!(tetris.score(), 400);
+    }
+
+    #[test]
+    fn pieces_stay_put_during_a_clear() {
+        let mut tetris = well(Speed::default());
+        fill_row(&mut tetris, 19);
+        assert!(tetris.start_clear());
+        let (shape, offset) = (tetris.now_shape.clone(), tetris.offset);
+        tetris.left_once();
+        tetris.right_once();
+        tetris.rotate();
+        tetris.down_once();
+        tetris.down_immediately();
+        assert_eq!(tetris.now_shape, shape);
+        assert_eq!(tetris.offset, offset);
+        assert!(tetris.now_shape().is_empty());
+    }
+
+    #[test]
+    fn rows_above_the_cleared_ones_move_down() {
+        let mut tetris = well(Speed::default());
+        fill_row(&mut tetris, 17);
+        fill_row(&mut tetris, 19);
+        for &p in &[Point(7, 15), Point(3, 16), Point(0, 18)] {
+            tetris.bottom.set(p, true);
+        }
+        assert!(tetris.start_clear());
+        assert_eq!(tetris.pending_clear().unwrap().lines, vec![17, 19]);
+        while tetris.is_clearing() {
+            assert_eq!(tetris.move_on(), Ok(()));
+        }
+        assert_eq!(tetris.bottom(), vec![Point(7, 17), Point(3, 18), Point(0, 19)]);
+    }
+
+    #[test]
+    fn reaching_the_top_row_ends_the_game() {
+        let mut tetris = well(Speed::default());
+        tetris.bottom.set(Point(0, 1), true);
+        tetris.now_shape = vec![Point(0, 0)];
+        tetris.offset = Point(0, 0);
+        assert!(tetris.move_on().is_err());
+    }
+
+    #[test]
+    fn locking_above_the_well_ends_the_game() {
+        let mut tetris = well(Speed::default());
+        // the part in the well completes the top row, which is then cleared
+        fill_row(&mut tetris, 0);
+        tetris.bottom.set(Point(0, 0), false);
+        tetris.bottom.set(Point(0, 1), true);
+        tetris.now_shape = vec![Point(0, -1), Point(0, 0)];
+        tetris.offset = Point(0, 0);
+        assert_eq!(tetris.move_on(), Ok(()));
+        assert_eq!(tetris.pending_clear().unwrap().lines, vec![0]);
+        assert_eq!(tetris.move_on(), Ok(()));
+        assert_eq!(tetris.move_on(), Ok(()));
+        assert!(tetris.move_on().is_err());
+        assert_eq!(tetris.bottom(), vec![Point(0, 1)]);
+    }
+
+    #[test]
+    fn hard_drop_during_a_clear_leaves_the_next_piece_alone() {
+        let speed = Speed { gravity_secs: 0.1, lock_delay_secs: 0.3, clear_secs: 0.3 };
+        let mut tetris = well(speed);
+        fill_row(&mut tetris, 19);
+        assert!(tetris.start_clear());
+        tetris.down_immediately();
+        while tetris.is_clearing() {
+            assert_eq!(tetris.move_on(), Ok(()));
+        }
+
+        // the next piece lands and can still put off locking by sliding
+        while !tetris.move_collides(Direction::Down) {
+            tetris.down_once();
+        }
+        assert_eq!(tetris.move_on(), Ok(()));
+        assert!(tetris.landed_secs > 0.0);
+        tetris.left_once();
+        assert_eq!(tetris.landed_secs, 0.0);
+        assert_eq!(tetris.lock_resets, 1);
+    }
+
+    #[test]
+    fn flash_lasts_about_the_same_at_every_gravity() {
+        for &(gravity_secs, frames) in &[(0.3, 2), (0.2, 3), (0.12, 5)] {
+            let speed = Speed { gravity_secs: gravity_secs, ..Speed::default() };
+            assert_eq!(well(speed).clear_frames(), frames);
+        }
+        let speed = Speed { gravity_secs: 1.0, ..Speed::default() };
+        assert_eq!(well(speed).clear_frames(), 1);
+    }
+}